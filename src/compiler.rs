@@ -0,0 +1,361 @@
+use crate::error::*;
+use crate::runtime::Variable;
+use crate::span::Span;
+use crate::tokenizer::*;
+use std::rc::Rc;
+
+// operands index into the tables of the `Function` the code belongs to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instr {
+    // push constants[i]
+    Const(usize),
+    // push the void value of a block without `~`
+    Void,
+    // push a variable, the name is for the error when it is not defined yet
+    Load(Slot, usize),
+    // pop into a slot of the current frame
+    Define(usize, usize),
+    Pop,
+    // push a lamda made from functions[i] over the current frame
+    Closure(usize),
+    // call the function under `n` arguments
+    Call(usize, usize),
+    // same, but reusing the frame of the function returning its result
+    TailCall(usize, usize),
+    Return,
+    // pops what the function returns, whatever the call in tail position
+    // after it gives back
+    Worth,
+    // open and close the frame of a `-> { }` scope
+    Enter,
+    Leave,
+    Jump(usize),
+    // pops the condition, anything but `TRUE` jumps
+    JumpIfFalse(usize),
+    Binary(JOINT),
+    // checks the boolean on top for `and` / `or`, when it is already the
+    // result the right hand side is jumped over
+    ShortCircuit(JOINT, usize),
+    Not,
+    Neg,
+    // makes a list of the top `n` values
+    List(usize),
+    Tuple(usize),
+    // pops a tuple of `n` items and pushes its items
+    Unpack(usize),
+    // makes a record of the top `n` name and value pairs, copying the
+    // record under them when the flag is set
+    Record(usize, bool),
+    Field(usize),
+    // tries patterns[i] on the value on top in a new frame : when it fits
+    // the value is popped and the frame entered, else it jumps
+    Match(usize, usize),
+    // pops the value no arm matched
+    Unmatched,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Function {
+    pub name: String,
+    // the source, kept to print lamdas the same way the tree walker does
+    pub args: Rc<Vec<String>>,
+    pub value: Rc<Vec<Tokens>>,
+    pub code: Vec<Instr>,
+    // span of the expression each instruction comes from
+    pub spans: Vec<Span>,
+    pub constants: Vec<Variable>,
+    pub functions: Vec<Rc<Function>>,
+    pub names: Vec<String>,
+    pub patterns: Vec<Pattern>,
+}
+
+pub struct Compiler {
+    function: Function,
+    // the first error, the code after it is not used
+    error: Option<TofError>,
+}
+
+impl Compiler {
+    fn new(name: &str, args: Rc<Vec<String>>, value: Rc<Vec<Tokens>>) -> Compiler {
+        Compiler {
+            function: Function {
+                name: name.to_string(),
+                args,
+                value,
+                code: Vec::new(),
+                spans: Vec::new(),
+                constants: Vec::new(),
+                functions: Vec::new(),
+                names: Vec::new(),
+                patterns: Vec::new(),
+            },
+            error: None,
+        }
+    }
+
+    // compiles resolved top level tokens, they run in the top level frame
+    pub fn script(tokens: &[Tokens]) -> TofResult<Function> {
+        let mut compiler = Compiler::new("<script>", Rc::new(Vec::new()), Rc::new(Vec::new()));
+        compiler.block(tokens, false);
+        let end = tokens.last().map(|t| t.span).unwrap_or_default();
+        compiler.emit(Instr::Return, end);
+        compiler.finish()
+    }
+
+    fn lamda(name: &str, lamda: &Lamda, span: Span) -> TofResult<Function> {
+        let mut compiler = Compiler::new(name, lamda.args.clone(), lamda.value.clone());
+        compiler.block(&lamda.value, true);
+        compiler.emit(Instr::Return, span);
+        compiler.finish()
+    }
+
+    fn finish(self) -> TofResult<Function> {
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.function),
+        }
+    }
+
+    fn emit(&mut self, instr: Instr, span: Span) -> usize {
+        self.function.code.push(instr);
+        self.function.spans.push(span);
+        self.function.code.len() - 1
+    }
+
+    fn patch(&mut self, at: usize) {
+        let here = self.function.code.len();
+        match &mut self.function.code[at] {
+            Instr::Jump(to)
+            | Instr::JumpIfFalse(to)
+            | Instr::ShortCircuit(_, to)
+            | Instr::Match(_, to) => *to = here,
+            _ => unreachable!(),
+        }
+    }
+
+    fn constant(&mut self, var: Variable) -> usize {
+        self.function.constants.push(var);
+        self.function.constants.len() - 1
+    }
+
+    fn name(&mut self, name: &str) -> usize {
+        match self.function.names.iter().position(|n| n == name) {
+            Some(i) => i,
+            None => {
+                self.function.names.push(name.to_string());
+                self.function.names.len() - 1
+            }
+        }
+    }
+
+    // leaves the value of the block on the stack : the value of its last
+    // `~`, which later statements leave untouched, or void
+    fn block(&mut self, tokens: &[Tokens], tail: bool) {
+        let last_return = tokens
+            .iter()
+            .rposition(|t| matches!(t.token, Token::Return(_)));
+
+        for (i, line) in tokens.iter().enumerate() {
+            match &line.token {
+                Token::Def(def) => {
+                    match &def.value.kind {
+                        ExprKind::Lamda(lamda) => self.closure(&def.name, lamda, def.value.span),
+                        _ => self.expr(&def.value, false),
+                    }
+                    let name = self.name(&def.name);
+                    self.emit(Instr::Define(def.slot, name), def.span);
+                }
+                Token::Unpack(unpack) => {
+                    self.expr(&unpack.value, false);
+                    self.emit(Instr::Unpack(unpack.names.len()), unpack.span);
+                    for (name, slot) in unpack.names.iter().zip(&unpack.slots).rev() {
+                        let name = self.name(name);
+                        self.emit(Instr::Define(*slot, name), unpack.span);
+                    }
+                }
+                Token::Type(def) => {
+                    for ((ctor, slot), span) in
+                        def.constructors.iter().zip(&def.slots).zip(&def.spans)
+                    {
+                        let i = self.constant(Variable::constructor(ctor));
+                        self.emit(Instr::Const(i), *span);
+                        let name = self.name(&ctor.name);
+                        self.emit(Instr::Define(*slot, name), *span);
+                    }
+                }
+                Token::Expr(expr) if tail && i == tokens.len() - 1 => {
+                    if last_return.is_none() {
+                        self.emit(Instr::Void, line.span);
+                    }
+                    self.emit(Instr::Worth, line.span);
+                    self.expr(expr, true);
+                    return;
+                }
+                Token::Return(expr) if Some(i) == last_return => {
+                    self.expr(expr, tail && i == tokens.len() - 1);
+                }
+                Token::Expr(expr) | Token::Return(expr) => {
+                    self.expr(expr, false);
+                    self.emit(Instr::Pop, line.span);
+                }
+            }
+        }
+        if last_return.is_none() {
+            let end = tokens.last().map(|t| t.span).unwrap_or_default();
+            self.emit(Instr::Void, end);
+        }
+    }
+
+    fn closure(&mut self, name: &str, lamda: &Lamda, span: Span) {
+        let function = match Compiler::lamda(name, lamda, span) {
+            Ok(function) => function,
+            Err(err) => {
+                self.error.get_or_insert(err);
+                return;
+            }
+        };
+        self.function.functions.push(Rc::new(function));
+        self.emit(Instr::Closure(self.function.functions.len() - 1), span);
+    }
+
+    fn expr(&mut self, expr: &Expr, tail: bool) {
+        let span = expr.span;
+        if let Err(err) = check_stack(span) {
+            self.error.get_or_insert(err);
+            return;
+        }
+        match &expr.kind {
+            ExprKind::Int(int) => {
+                let i = self.constant(Variable::Int(*int));
+                self.emit(Instr::Const(i), span);
+            }
+            ExprKind::BigInt(int) => {
+                let i = self.constant(Variable::BigInt(int.clone()));
+                self.emit(Instr::Const(i), span);
+            }
+            ExprKind::Rational(ratio) => {
+                let i = self.constant(Variable::Rational(ratio.clone()));
+                self.emit(Instr::Const(i), span);
+            }
+            ExprKind::Float(float) => {
+                let i = self.constant(Variable::Float(*float));
+                self.emit(Instr::Const(i), span);
+            }
+            ExprKind::Str(string) => {
+                let i = self.constant(Variable::Str(string.clone()));
+                self.emit(Instr::Const(i), span);
+            }
+            ExprKind::Bool(bool) => {
+                let i = self.constant(Variable::Bool(*bool));
+                self.emit(Instr::Const(i), span);
+            }
+            ExprKind::Lamda(lamda) => self.closure("<lamda>", lamda, span),
+            ExprKind::FcCall(FcCall { args, name, slot }) => {
+                let name = self.name(name);
+                self.emit(Instr::Load(*slot, name), span);
+                for arg in args {
+                    self.expr(arg, false);
+                }
+                if tail {
+                    self.emit(Instr::TailCall(args.len(), name), span);
+                } else {
+                    self.emit(Instr::Call(args.len(), name), span);
+                }
+            }
+            ExprKind::Call(Var { name, slot }) => {
+                let name = self.name(name);
+                self.emit(Instr::Load(*slot, name), span);
+            }
+            ExprKind::Op(Op { joint, lhs, rhs }) if joint.short_circuits() => {
+                self.expr(lhs, false);
+                let end = self.emit(Instr::ShortCircuit(*joint, 0), span);
+                self.expr(rhs, false);
+                self.emit(Instr::Binary(*joint), span);
+                self.patch(end);
+            }
+            ExprKind::Not(expr) => {
+                self.expr(expr, false);
+                self.emit(Instr::Not, span);
+            }
+            ExprKind::Neg(expr) => {
+                self.expr(expr, false);
+                self.emit(Instr::Neg, span);
+            }
+            ExprKind::List(items) => {
+                for item in items {
+                    self.expr(item, false);
+                }
+                self.emit(Instr::List(items.len()), span);
+            }
+            ExprKind::Tuple(items) => {
+                for item in items {
+                    self.expr(item, false);
+                }
+                self.emit(Instr::Tuple(items.len()), span);
+            }
+            ExprKind::Record(Record { base, fields }) => {
+                if let Some(base) = base {
+                    self.expr(base, false);
+                }
+                for (name, value) in fields {
+                    let i = self.constant(Variable::Str(name.clone()));
+                    self.emit(Instr::Const(i), value.span);
+                    self.expr(value, false);
+                }
+                self.emit(Instr::Record(fields.len(), base.is_some()), span);
+            }
+            ExprKind::Field(Field { record, name }) => {
+                self.expr(record, false);
+                let name = self.name(name);
+                self.emit(Instr::Field(name), span);
+            }
+            ExprKind::Op(Op { joint, lhs, rhs }) => {
+                self.expr(lhs, false);
+                self.expr(rhs, false);
+                self.emit(Instr::Binary(*joint), span);
+            }
+            ExprKind::Decision(decision) => self.decision(decision, tail),
+            ExprKind::Match(m) => self.arms(m, tail),
+            ExprKind::Scope(tokens) => {
+                self.emit(Instr::Enter, span);
+                self.block(tokens, tail);
+                self.emit(Instr::Leave, span);
+            }
+        }
+    }
+
+    fn arms(&mut self, m: &Match, tail: bool) {
+        let span = m.value.span;
+        self.expr(&m.value, false);
+        let mut ends = Vec::new();
+        for arm in &m.arms {
+            self.function.patterns.push(arm.pattern.clone());
+            let pattern = self.function.patterns.len() - 1;
+            let next = self.emit(Instr::Match(pattern, 0), span);
+            self.block(&arm.body, tail);
+            self.emit(Instr::Leave, arm.pattern.span);
+            ends.push(self.emit(Instr::Jump(0), arm.pattern.span));
+            self.patch(next);
+        }
+        self.emit(Instr::Unmatched, span);
+        for end in ends {
+            self.patch(end);
+        }
+    }
+
+    fn decision(&mut self, decision: &Decision, tail: bool) {
+        let span = decision.cond.span;
+        self.expr(&decision.cond, false);
+        let next = self.emit(Instr::JumpIfFalse(0), span);
+        self.block(&decision.block, tail);
+        let end = self.emit(Instr::Jump(0), span);
+        self.patch(next);
+        match &decision.next {
+            Some(next) => self.decision(next, tail),
+            None => {
+                self.emit(Instr::Void, span);
+            }
+        }
+        self.patch(end);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::error::TofError;
+use crate::span::SourceMap;
+use std::io::IsTerminal;
+
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+// how many calls of a stack trace are shown
+const TRACE_LEN: usize = 20;
+
+pub fn use_color() -> bool {
+    std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal()
+}
+
+struct Paint {
+    color: bool,
+}
+
+impl Paint {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if self.color {
+            format!("{}{}{}", style, text, RESET)
+        } else {
+            text.to_string()
+        }
+    }
+}
+
+// renders an error the way rustc does :
+//
+// type error: you can only add numbers and string
+//  --> example.tof:3:9
+//   |
+// 3 |   print (1 + "a")
+//   |          ^^^^^^^
+//   = help: ...
+//   = note: in fib, called at example.tof:7:20
+//   = note: fib called 23 more times
+pub fn render(sources: &SourceMap, err: &TofError, color: bool) -> String {
+    let p = Paint { color };
+    // notes line up with the `|` of the source, if any
+    let mut indent = String::new();
+    let mut out = format!(
+        "{}{}\n",
+        p.paint(RED, err.category()),
+        p.paint(BOLD, &format!(": {}", err.message))
+    );
+
+    if let Some(span) = err.span {
+        let file = sources.get(span.file);
+        let (line, col) = file.line_col(span.start);
+        let (end_line, end_col) = file.line_col(span.end);
+        let text = file.line(line);
+
+        let width = line.to_string().len();
+        let pad = " ".repeat(width);
+        indent = format!("{} ", pad);
+        let bar = p.paint(BLUE, "|");
+
+        // spans running over several lines are underlined to the end of
+        // their first line
+        let len = if end_line == line {
+            end_col.saturating_sub(col).max(1)
+        } else {
+            text.chars().count().saturating_sub(col - 1).max(1)
+        };
+
+        out += &format!(
+            "{}{} {}:{}:{}\n",
+            pad,
+            p.paint(BLUE, "-->"),
+            file.name,
+            line,
+            col
+        );
+        out += &format!("{} {}\n", pad, bar);
+        out += &format!("{} {} {}\n", p.paint(BLUE, &line.to_string()), bar, text);
+        out += &format!(
+            "{} {} {}{}\n",
+            pad,
+            bar,
+            " ".repeat(col - 1),
+            p.paint(RED, &"^".repeat(len))
+        );
+    }
+
+    let note = |label: &str, text: &str| {
+        format!(
+            "{}{} {}\n",
+            indent,
+            p.paint(BLUE, "="),
+            p.paint(BOLD, label) + " " + text
+        )
+    };
+    if let Some(help) = &err.help {
+        out += &note("help:", help);
+    }
+
+    // innermost call first, calls a function makes to itself are counted
+    // instead of listed
+    let mut rest = &err.trace[..];
+    let mut shown = 0;
+    while let Some(call) = rest.first() {
+        if shown == TRACE_LEN {
+            out += &note("note:", &format!("and {} more calls", rest.len()));
+            break;
+        }
+        let file = sources.get(call.span.file);
+        let (line, col) = file.line_col(call.span.start);
+        out += &note(
+            "note:",
+            &format!("in {}, called at {}:{}:{}", call.name, file.name, line, col),
+        );
+
+        let same = rest.iter().take_while(|c| c.name == call.name).count();
+        match same - 1 {
+            0 => {}
+            1 => out += &note("note:", &format!("{} called 1 more time", call.name)),
+            more => {
+                out += &note(
+                    "note:",
+                    &format!("{} called {} more times", call.name, more),
+                )
+            }
+        }
+        rest = &rest[same..];
+        shown += 1;
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CallSite;
+    use crate::span::Span;
+
+    const SRC: &str = "x : 1\nprint (1 + \"a\")\ny : (2 +\n  3)\n";
+
+    fn sources() -> SourceMap {
+        let mut sources = SourceMap::new();
+        sources.add("test.tof", SRC);
+        sources
+    }
+
+    // the span of the first `text` in `SRC`
+    fn span(text: &str) -> Span {
+        let start = SRC.find(text).unwrap();
+        Span::new(0, start, start + text.len())
+    }
+
+    #[test]
+    fn points_at_the_span() {
+        let err = TofError::type_error("you can only add numbers and string".to_string())
+            .at(span("1 + \"a\""))
+            .with_help("convert one of them");
+        assert_eq!(
+            render(&sources(), &err, false),
+            "\
+type error: you can only add numbers and string
+ --> test.tof:2:8
+  |
+2 | print (1 + \"a\")
+  |        ^^^^^^^
+  = help: convert one of them
+"
+        );
+    }
+
+    #[test]
+    fn spans_over_several_lines_are_underlined_to_the_end_of_the_first() {
+        let err = TofError::parse("expected an expression".to_string()).at(span("(2 +\n  3)"));
+        assert_eq!(
+            render(&sources(), &err, false),
+            "\
+parse error: expected an expression
+ --> test.tof:3:5
+  |
+3 | y : (2 +
+  |     ^^^^
+"
+        );
+    }
+
+    #[test]
+    fn an_empty_span_gets_one_caret() {
+        let end = SRC.len();
+        let err = TofError::parse("expected `)`".to_string()).at(Span::new(0, end, end));
+        assert!(render(&sources(), &err, false).ends_with("5 | \n  | ^\n"));
+    }
+
+    #[test]
+    fn errors_without_a_span_are_one_line() {
+        let err = TofError::io("cannot read a.tof".to_string());
+        assert_eq!(
+            render(&sources(), &err, false),
+            "io error: cannot read a.tof\n"
+        );
+    }
+
+    #[test]
+    fn color_is_only_used_when_asked_for() {
+        let err = TofError::name("variable not in scope z".to_string()).at(span("x"));
+        assert!(!render(&sources(), &err, false).contains('\x1b'));
+        assert!(render(&sources(), &err, true).starts_with("\x1b[1;31mname error\x1b[0m"));
+    }
+
+    fn calls(names: &[&str]) -> Vec<CallSite> {
+        names
+            .iter()
+            .map(|name| CallSite {
+                name: name.to_string(),
+                span: span("print"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn calls_a_function_makes_to_itself_are_counted() {
+        let err = TofError::arithmetic("division by zero".to_string())
+            .at(span("1 + \"a\""))
+            .with_trace(calls(&["g", "f", "f", "f", "main", "main"]));
+        let notes: Vec<_> = render(&sources(), &err, false)
+            .lines()
+            .skip(5)
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                "  = note: in g, called at test.tof:2:1",
+                "  = note: in f, called at test.tof:2:1",
+                "  = note: f called 2 more times",
+                "  = note: in main, called at test.tof:2:1",
+                "  = note: main called 1 more time",
+            ]
+        );
+    }
+
+    #[test]
+    fn long_traces_are_cut() {
+        let names: Vec<String> = (0..TRACE_LEN + 5).map(|i| format!("f{}", i)).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let err = TofError::recursion("too deep".to_string()).with_trace(calls(&names));
+        let out = render(&sources(), &err, false);
+        assert_eq!(out.matches("= note: in ").count(), TRACE_LEN);
+        assert!(out.contains(&format!("in f{},", TRACE_LEN - 1)));
+        assert!(out.ends_with("= note: and 5 more calls\n"));
+    }
+}
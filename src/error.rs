@@ -0,0 +1,201 @@
+use crate::span::Span;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Lex,
+    Parse,
+    Name,
+    Type,
+    Arity,
+    Arithmetic,
+    Recursion,
+    // a list read past its end
+    Index,
+    // a `match` without an arm for the value
+    Match,
+    Io,
+}
+
+// a tof function being run and the span of the call that started it
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallSite {
+    pub name: String,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TofError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub span: Option<Span>,
+    pub help: Option<String>,
+    // the calls the error was raised in, innermost first
+    pub trace: Vec<CallSite>,
+}
+
+pub type TofResult<T> = Result<T, TofError>;
+
+// how much of the stack is kept free. the parser, the resolver and the
+// compiler recurse once per nested expression and stop with an error when
+// less than this is left, instead of overflowing
+pub const STACK_RED_ZONE: usize = 1024 * 1024;
+
+pub fn check_stack(span: Span) -> TofResult<()> {
+    if stacker::remaining_stack().is_some_and(|left| left < STACK_RED_ZONE) {
+        return Err(
+            TofError::recursion("expressions nest too deeply".to_string())
+                .at(span)
+                .with_help("give parts of the expression names with `name : value`"),
+        );
+    }
+    Ok(())
+}
+
+impl TofError {
+    pub fn new(kind: ErrorKind, message: String) -> TofError {
+        TofError {
+            kind,
+            message,
+            span: None,
+            help: None,
+            trace: Vec::new(),
+        }
+    }
+
+    pub fn lex(message: String) -> TofError {
+        TofError::new(ErrorKind::Lex, message)
+    }
+
+    pub fn parse(message: String) -> TofError {
+        TofError::new(ErrorKind::Parse, message)
+    }
+
+    pub fn name(message: String) -> TofError {
+        TofError::new(ErrorKind::Name, message)
+    }
+
+    pub fn type_error(message: String) -> TofError {
+        TofError::new(ErrorKind::Type, message)
+    }
+
+    pub fn arity(message: String) -> TofError {
+        TofError::new(ErrorKind::Arity, message)
+    }
+
+    pub fn arithmetic(message: String) -> TofError {
+        TofError::new(ErrorKind::Arithmetic, message)
+    }
+
+    pub fn recursion(message: String) -> TofError {
+        TofError::new(ErrorKind::Recursion, message)
+    }
+
+    pub fn index(message: String) -> TofError {
+        TofError::new(ErrorKind::Index, message)
+    }
+
+    pub fn match_error(message: String) -> TofError {
+        TofError::new(ErrorKind::Match, message)
+    }
+
+    pub fn io(message: String) -> TofError {
+        TofError::new(ErrorKind::Io, message)
+    }
+
+    // the errors both engines raise, built here so that they read the same
+
+    pub fn undefined(name: &str) -> TofError {
+        TofError::name(format!("{} is used before it is defined", name))
+            .with_help("move the definition above this line")
+    }
+
+    pub fn wrong_arity(name: &str, expected: usize, given: usize) -> TofError {
+        TofError::arity(format!(
+            "{} takes {} argument(s) but {} were given",
+            name, expected, given
+        ))
+    }
+
+    pub fn void_definition(name: &str) -> TofError {
+        TofError::type_error(format!("you cannot store void value in {}", name))
+            .with_help("the right hand side must return a value, use `~` to return from a block")
+    }
+
+    pub fn void_operand() -> TofError {
+        TofError::type_error("you cannot use void value in an operation".to_string())
+    }
+
+    pub fn void_argument(name: &str) -> TofError {
+        TofError::type_error(format!("sorry you cannot pass void value to {}", name))
+    }
+
+    pub fn void_item(kind: &str) -> TofError {
+        TofError::type_error(format!("you cannot put void value in a {}", kind))
+            .with_help("the item must return a value, use `~` to return from a block")
+    }
+
+    pub fn void_field(name: &str) -> TofError {
+        TofError::type_error(format!("you cannot store void value in field {}", name))
+            .with_help("the field must return a value, use `~` to return from a block")
+    }
+
+    pub fn void_match() -> TofError {
+        TofError::type_error("you cannot match void value".to_string()).with_help(
+            "the value after `match` must return a value, use `~` to return from a block",
+        )
+    }
+
+    pub fn no_match(type_name: &str) -> TofError {
+        TofError::match_error(format!("no pattern matches this {}", type_name))
+            .with_help("add a last arm `_ -> ...` for every other value")
+    }
+
+    // keeps the innermost span, so an error raised deep inside an
+    // expression still points at the exact sub-expression
+    pub fn at(mut self, span: Span) -> TofError {
+        if self.span.is_none() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    pub fn with_help(mut self, help: &str) -> TofError {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn with_trace(mut self, trace: Vec<CallSite>) -> TofError {
+        self.trace = trace;
+        self
+    }
+
+    pub fn category(&self) -> &'static str {
+        match self.kind {
+            ErrorKind::Lex => "lex error",
+            ErrorKind::Parse => "parse error",
+            ErrorKind::Name => "name error",
+            ErrorKind::Type => "type error",
+            ErrorKind::Arity => "arity error",
+            ErrorKind::Arithmetic => "arithmetic error",
+            ErrorKind::Recursion => "recursion error",
+            ErrorKind::Index => "index error",
+            ErrorKind::Match => "match error",
+            ErrorKind::Io => "io error",
+        }
+    }
+}
+
+impl fmt::Display for TofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} : {}", self.category(), self.message)
+    }
+}
+
+impl std::error::Error for TofError {}
+
+impl From<std::io::Error> for TofError {
+    fn from(err: std::io::Error) -> TofError {
+        TofError::io(err.to_string())
+    }
+}
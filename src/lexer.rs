@@ -0,0 +1,419 @@
+use super::error::*;
+use super::span::{FileId, Span};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LexKind {
+    Ident(String),
+    Int(i64),
+    // an int literal too large for an `Int`
+    BigInt(BigInt),
+    Float(f64),
+    // an int with an `r` after it, like `3r`
+    Rational(BigRational),
+    Str(String),
+    Operator(&'static str),
+    Punct(&'static str),
+    Newline,
+    Comment(String),
+    // a bad number or character, already reported. the parser takes it as
+    // an operand or an operator so that it does not report it again
+    Error,
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lexeme {
+    pub kind: LexKind,
+    pub span: Span,
+}
+
+// longest first, so that `->` wins over `-`
+const PUNCTS: [&str; 14] = [
+    "->", "||", "|", "(", ")", "{", "}", "[", "]", ",", ".", ":", "?", "~",
+];
+const OPERATORS: [&str; 15] = [
+    "**", "//", "==", "!=", ">=", "<=", "+", "-", "*", "/", "%", ">", "<", "=", "!",
+];
+// words lexed as operators, they cannot be used as names
+const KEYWORDS: [&str; 3] = ["and", "or", "not"];
+
+pub struct Lexer<'a> {
+    pub lexemes: Vec<Lexeme>,
+    pub errors: Vec<TofError>,
+    src: &'a str,
+    coverage: usize,
+    file: FileId,
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(src: &'a str, file: FileId) -> Lexer<'a> {
+        Lexer {
+            lexemes: Vec::new(),
+            errors: Vec::new(),
+            src,
+            coverage: 0,
+            file,
+        }
+    }
+
+    // lexing never stops at a bad character, errors are collected and an
+    // `Error` lexeme takes its place so that the parser still sees the rest
+    // of the file
+    pub fn start(&mut self) {
+        while let Some(c) = self.peek() {
+            let start = self.coverage;
+            let kind = if c == '\n' {
+                self.coverage += 1;
+                LexKind::Newline
+            } else if c.is_whitespace() {
+                self.coverage += c.len_utf8();
+                continue;
+            } else if self.rest().starts_with("//") && !self.after_operand() {
+                let len = self.rest().find('\n').unwrap_or_else(|| self.rest().len());
+                let text = self.rest()[2..len].to_string();
+                self.coverage += len;
+                LexKind::Comment(text)
+            } else if c == '"' {
+                self.string_resolver()
+            } else if c.is_ascii_digit() {
+                match self.number_resolver() {
+                    Ok(kind) => kind,
+                    Err(err) => {
+                        self.errors.push(err);
+                        LexKind::Error
+                    }
+                }
+            } else if c.is_alphabetic() || c == '_' {
+                let len = self
+                    .rest()
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or_else(|| self.rest().len());
+                let ident = &self.rest()[..len];
+                self.coverage += len;
+                match KEYWORDS.iter().find(|k| **k == ident) {
+                    Some(keyword) => LexKind::Operator(keyword),
+                    None => LexKind::Ident(ident.to_string()),
+                }
+            } else if let Some(punct) = PUNCTS.iter().find(|p| self.rest().starts_with(*p)) {
+                self.coverage += punct.len();
+                LexKind::Punct(punct)
+            } else if let Some(op) = OPERATORS.iter().find(|p| self.rest().starts_with(*p)) {
+                self.coverage += op.len();
+                LexKind::Operator(op)
+            } else {
+                self.coverage += c.len_utf8();
+                self.errors.push(
+                    TofError::lex(format!("unexpected character `{}`", c))
+                        .at(self.span(start, self.coverage)),
+                );
+                LexKind::Error
+            };
+            self.lexemes.push(Lexeme {
+                kind,
+                span: self.span(start, self.coverage),
+            });
+        }
+        self.lexemes.push(Lexeme {
+            kind: LexKind::Eof,
+            span: self.span(self.coverage, self.coverage),
+        });
+    }
+
+    // `//` right after a value is floor division, anywhere else it starts a
+    // comment
+    fn after_operand(&self) -> bool {
+        matches!(
+            self.lexemes.last().map(|l| &l.kind),
+            Some(LexKind::Ident(_))
+                | Some(LexKind::Int(_))
+                | Some(LexKind::BigInt(_))
+                | Some(LexKind::Float(_))
+                | Some(LexKind::Rational(_))
+                | Some(LexKind::Str(_))
+                | Some(LexKind::Punct(")"))
+                | Some(LexKind::Punct("]"))
+        )
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.src[self.coverage..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn span(&self, start: usize, end: usize) -> Span {
+        Span::new(self.file, start, end)
+    }
+
+    // strings end at the line, use `\n` for a newline inside one
+    fn string_resolver(&mut self) -> LexKind {
+        let start = self.coverage;
+        self.coverage += 1;
+        let mut string = String::new();
+        loop {
+            let c = match self.peek() {
+                Some(c) if c != '\n' => c,
+                _ => {
+                    self.errors.push(
+                        TofError::lex("unterminated string".to_string())
+                            .at(self.span(start, start + 1))
+                            .with_help("close the string with `\"`"),
+                    );
+                    break;
+                }
+            };
+            self.coverage += c.len_utf8();
+            match c {
+                '"' => break,
+                '\\' => {
+                    let escaped = match self.peek() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some(c) if c != '\n' => {
+                            self.errors
+                                .push(TofError::lex(format!("unknown escape `\\{}`", c)).at(
+                                    self.span(self.coverage - 1, self.coverage + c.len_utf8()),
+                                ));
+                            c
+                        }
+                        _ => continue,
+                    };
+                    self.coverage += escaped.len_utf8();
+                    string.push(escaped);
+                }
+                _ => string.push(c),
+            }
+        }
+        LexKind::Str(string)
+    }
+
+    // `3` and `1_000` are ints, `3r` a rational. a number with a fraction or
+    // an exponent, like `2.5` or `1e-3`, is a float
+    fn number_resolver(&mut self) -> TofResult<LexKind> {
+        let start = self.coverage;
+        let rest = self.rest();
+
+        // `t.0.1` reads two items of nested tuples, it is not `t.(0.1)`
+        if let Some(LexKind::Punct(".")) = self.lexemes.last().map(|l| &l.kind) {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            self.coverage += len;
+            return match rest[..len].parse() {
+                Ok(num) => Ok(LexKind::Int(num)),
+                Err(_) => Err(TofError::lex(format!("invalid number `{}`", &rest[..len]))
+                    .at(self.span(start, self.coverage))),
+            };
+        }
+
+        let mut len = digits(rest);
+        let mut float = false;
+        let after = &rest[len..];
+        if after.starts_with('.') && after[1..].starts_with(|c: char| c.is_ascii_digit()) {
+            len += 1 + digits(&after[1..]);
+            float = true;
+        }
+        let after = &rest[len..];
+        if after.starts_with(['e', 'E']) {
+            let sign = usize::from(after[1..].starts_with(['+', '-']));
+            if after[1 + sign..].starts_with(|c: char| c.is_ascii_digit()) {
+                len += 1 + sign + digits(&after[1 + sign..]);
+                float = true;
+            }
+        }
+        let rational = !float
+            && rest[len..].starts_with('r')
+            && !rest[len + 1..].starts_with(|c: char| c.is_alphanumeric() || c == '_');
+
+        let text = rest[..len].replace('_', "");
+        self.coverage += len + usize::from(rational);
+        let span = self.span(start, self.coverage);
+        let invalid = || TofError::lex(format!("invalid number `{}`", &rest[..len])).at(span);
+        if rational {
+            match text.parse() {
+                Ok(num) => Ok(LexKind::Rational(BigRational::from_integer(num))),
+                Err(_) => Err(invalid()),
+            }
+        } else if float {
+            match text.parse::<f64>() {
+                Ok(num) if num.is_finite() => Ok(LexKind::Float(num)),
+                Ok(_) => Err(
+                    TofError::lex(format!("`{}` is too large for a float", &rest[..len])).at(span),
+                ),
+                Err(_) => Err(invalid()),
+            }
+        } else {
+            match text.parse() {
+                Ok(num) => Ok(LexKind::Int(num)),
+                Err(_) => match text.parse() {
+                    Ok(num) => Ok(LexKind::BigInt(num)),
+                    Err(_) => Err(invalid()),
+                },
+            }
+        }
+    }
+}
+
+// length of the digits at the start of `s`, a `_` between two digits is
+// part of them
+fn digits(s: &str) -> usize {
+    let bytes = s.as_bytes();
+    let mut len = 0;
+    while let Some(&b) = bytes.get(len) {
+        let separator = b == b'_'
+            && len > 0
+            && bytes[len - 1].is_ascii_digit()
+            && bytes.get(len + 1).is_some_and(u8::is_ascii_digit);
+        if !(b.is_ascii_digit() || separator) {
+            break;
+        }
+        len += 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(src: &str) -> Vec<LexKind> {
+        let mut lexer = Lexer::new(src, 0);
+        lexer.start();
+        assert_eq!(lexer.errors, [], "{} has errors", src);
+        lexer.lexemes.into_iter().map(|l| l.kind).collect()
+    }
+
+    fn errors(src: &str) -> Vec<String> {
+        let mut lexer = Lexer::new(src, 0);
+        lexer.start();
+        lexer.errors.into_iter().map(|err| err.message).collect()
+    }
+
+    fn ident(name: &str) -> LexKind {
+        LexKind::Ident(name.to_string())
+    }
+
+    #[test]
+    fn a_colon_inside_a_string_is_part_of_it() {
+        assert_eq!(
+            lex("x : \"a : b\""),
+            [
+                ident("x"),
+                LexKind::Punct(":"),
+                LexKind::Str("a : b".to_string()),
+                LexKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn slashes_after_a_value_divide() {
+        assert_eq!(
+            lex("7 // 2"),
+            [
+                LexKind::Int(7),
+                LexKind::Operator("//"),
+                LexKind::Int(2),
+                LexKind::Eof,
+            ]
+        );
+        assert_eq!(
+            lex("x : 1 + // note\n"),
+            [
+                ident("x"),
+                LexKind::Punct(":"),
+                LexKind::Int(1),
+                LexKind::Operator("+"),
+                LexKind::Comment(" note".to_string()),
+                LexKind::Newline,
+                LexKind::Eof,
+            ]
+        );
+        assert_eq!(
+            lex("// note"),
+            [LexKind::Comment(" note".to_string()), LexKind::Eof]
+        );
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(
+            lex(r#""a\n\t\"\\""#),
+            [LexKind::Str("a\n\t\"\\".to_string()), LexKind::Eof]
+        );
+        assert_eq!(errors(r#""\q""#), ["unknown escape `\\q`"]);
+    }
+
+    #[test]
+    fn unterminated_strings_end_at_the_line() {
+        let mut lexer = Lexer::new("\"open\nx", 0);
+        lexer.start();
+        assert_eq!(lexer.errors.len(), 1);
+        assert_eq!(lexer.errors[0].message, "unterminated string");
+        let kinds: Vec<_> = lexer.lexemes.into_iter().map(|l| l.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                LexKind::Str("open".to_string()),
+                LexKind::Newline,
+                ident("x"),
+                LexKind::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(
+            lex("1_000 2.5 1e-3 3r 1e3"),
+            [
+                LexKind::Int(1000),
+                LexKind::Float(2.5),
+                LexKind::Float(1e-3),
+                LexKind::Rational(BigRational::from_integer(3.into())),
+                LexKind::Float(1000.0),
+                LexKind::Eof,
+            ]
+        );
+        assert_eq!(
+            lex("99999999999999999999"),
+            [
+                LexKind::BigInt("99999999999999999999".parse().unwrap()),
+                LexKind::Eof,
+            ]
+        );
+        // not a rational, not an exponent
+        assert_eq!(
+            lex("3rd 2e"),
+            [
+                LexKind::Int(3),
+                ident("rd"),
+                LexKind::Int(2),
+                ident("e"),
+                LexKind::Eof,
+            ]
+        );
+        assert_eq!(errors("1e400"), ["`1e400` is too large for a float"]);
+    }
+
+    #[test]
+    fn items_of_nested_tuples() {
+        assert_eq!(
+            lex("t.0.1"),
+            [
+                ident("t"),
+                LexKind::Punct("."),
+                LexKind::Int(0),
+                LexKind::Punct("."),
+                LexKind::Int(1),
+                LexKind::Eof,
+            ]
+        );
+    }
+}
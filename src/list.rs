@@ -0,0 +1,133 @@
+use crate::runtime::{drop_deep, Variable};
+use std::fmt;
+use std::iter::FromIterator;
+use std::rc::Rc;
+
+// an immutable singly linked list. `tail` and `cons` share the nodes of the
+// list they start from instead of copying it, so both are O(1)
+#[derive(Clone, Default)]
+pub struct List {
+    head: Option<Rc<Cons>>,
+    len: usize,
+}
+
+pub struct Cons {
+    value: Variable,
+    next: List,
+}
+
+impl List {
+    pub fn new() -> List {
+        List::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn cons(&self, value: Variable) -> List {
+        List {
+            head: Some(Rc::new(Cons {
+                value,
+                next: self.clone(),
+            })),
+            len: self.len + 1,
+        }
+    }
+
+    pub fn head(&self) -> Option<&Variable> {
+        self.head.as_ref().map(|node| &node.value)
+    }
+
+    pub fn tail(&self) -> Option<List> {
+        self.head.as_ref().map(|node| node.next.clone())
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Variable> {
+        self.iter().nth(index)
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            next: self.head.as_deref(),
+        }
+    }
+
+    pub fn first(&self) -> Option<&Rc<Cons>> {
+        self.head.as_ref()
+    }
+
+    // moves out the items of the nodes no other list shares, leaving the
+    // list empty
+    pub fn take_items(&mut self, items: &mut Vec<Variable>) {
+        self.len = 0;
+        let mut head = self.head.take();
+        while let Some(node) = head {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => {
+                    head = node.next.head.take();
+                    items.push(node.value);
+                }
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+impl Cons {
+    pub fn value(&self) -> &Variable {
+        &self.value
+    }
+
+    pub fn next(&self) -> &List {
+        &self.next
+    }
+}
+
+// dropping node by node, the default drop recurses once per element and
+// overflows the stack on long lists. the items may nest as deep, see
+// `drop_deep`
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut items = Vec::new();
+        self.take_items(&mut items);
+        drop_deep(items);
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &List) -> bool {
+        self.len == other.len && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<Variable> for List {
+    fn from_iter<I: IntoIterator<Item = Variable>>(iter: I) -> List {
+        let values: Vec<Variable> = iter.into_iter().collect();
+        values
+            .into_iter()
+            .rev()
+            .fold(List::new(), |list, value| list.cons(value))
+    }
+}
+
+pub struct Iter<'a> {
+    next: Option<&'a Cons>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Variable;
+
+    fn next(&mut self) -> Option<&'a Variable> {
+        self.next.map(|node| {
+            self.next = node.next.head.as_deref();
+            &node.value
+        })
+    }
+}
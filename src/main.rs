@@ -1,6 +1,3 @@
-#[macro_use]
-extern crate lazy_static;
-
 use rustyline::error::ReadlineError;
 use rustyline::Editor;
 
@@ -26,7 +23,7 @@ enum Subcommand {
     Run(Run),
 
     #[clap(about = "to enter interactive mode")]
-    Play,
+    Play(Play),
 }
 
 #[derive(Clap, Debug)]
@@ -37,50 +34,141 @@ struct Run {
 
     #[clap(long, about = "show generated tokens", short)]
     show_tokens: bool,
+
+    #[clap(long, about = "run on the bytecode vm instead of the tree walker")]
+    vm: bool,
+
+    #[clap(long, about = "how deep calls can nest, 10000 by default")]
+    max_depth: Option<usize>,
+}
+
+#[derive(Clap, Debug)]
+#[clap(setting = AppSettings::ColoredHelp)]
+struct Play {
+    #[clap(long, about = "how deep calls can nest, 10000 by default")]
+    max_depth: Option<usize>,
 }
 
+mod compiler;
+mod diagnostic;
+mod error;
+mod lexer;
+mod list;
 mod prelude;
+mod resolver;
 mod runtime;
+mod span;
 mod tokenizer;
+mod vm;
 
-use runtime::Runtime;
+use error::*;
+use resolver::Resolver;
+use runtime::{Runtime, MAX_DEPTH};
+use span::SourceMap;
+use std::thread;
 
-mod utils;
 use tokenizer::Tokenizer;
+use vm::Vm;
+
+// the parser, the resolver and the compiler recurse once per nested
+// expression on this stack, it bounds how deep expressions can nest. the tree
+// walker grows its stack as calls nest, see `STACK_GROWTH`
+const STACK_SIZE: usize = 64 * 1024 * 1024;
+
+fn with_stack<F: FnOnce() + Send + 'static>(f: F) {
+    let handle = match thread::Builder::new().stack_size(STACK_SIZE).spawn(f) {
+        Ok(handle) => handle,
+        Err(err) => {
+            eprintln!("cannot make a thread to run on : {}", err);
+            std::process::exit(1);
+        }
+    };
+    if let Err(panic) = handle.join() {
+        std::panic::resume_unwind(panic);
+    }
+}
+
+fn report(sources: &SourceMap, errors: &[TofError]) {
+    let color = diagnostic::use_color();
+    for err in errors {
+        eprint!("{}", diagnostic::render(sources, err, color));
+    }
+}
+
+fn run(sources: &mut SourceMap, opts: &Run) -> Result<(), Vec<TofError>> {
+    let name = format!("{}.tof", opts.file);
+    let string = fs::read_to_string(&name)
+        .map_err(|err| vec![TofError::io(format!("cannot read {} : {}", name, err))])?;
+    let file = sources.add(&name, &string);
+    let mut tokenizer = Tokenizer::new(&string, file);
+    let parsed = tokenizer.start();
+    if opts.show_tokens {
+        println!("{:#?}", tokenizer.tokens);
+    }
+    parsed?;
+    Resolver::new().start(&mut tokenizer.tokens)?;
+    let max_depth = opts.max_depth.unwrap_or(MAX_DEPTH);
+    if opts.vm {
+        Vm::new()
+            .with_max_depth(max_depth)
+            .start(&tokenizer.tokens)
+            .map_err(|err| vec![err])?;
+    } else {
+        Runtime::new()
+            .with_max_depth(max_depth)
+            .start(tokenizer.tokens)
+            .map_err(|err| vec![err])?;
+    }
+    Ok(())
+}
+
+fn play_line(
+    sources: &mut SourceMap,
+    resolver: &mut Resolver,
+    runtime: &mut Runtime,
+    line: &str,
+) -> Result<(), Vec<TofError>> {
+    let file = sources.add("<play>", line);
+    let mut lexer = Tokenizer::new(line, file);
+    lexer.start()?;
+    resolver.start(&mut lexer.tokens)?;
+
+    runtime.start(lexer.tokens).map_err(|err| vec![err])?;
+    Ok(())
+}
 
 fn main() {
     let matches: Opts = Opts::parse();
 
     match matches.subcommand {
-        Subcommand::Run(Run { file, show_tokens }) => {
-            let string = fs::read_to_string(format!("{}.tof", file)).expect("file not found");
-            let mut tokenizer = Tokenizer::new(&string);
-            tokenizer.start();
-            if show_tokens {
-                println!("{:#?}", tokenizer.tokens);
+        Subcommand::Run(opts) => with_stack(move || {
+            let mut sources = SourceMap::new();
+            if let Err(errors) = run(&mut sources, &opts) {
+                report(&sources, &errors);
+                if errors.len() > 1 {
+                    eprintln!("aborting due to {} previous errors", errors.len());
+                }
+                std::process::exit(1);
             }
-            let mut runtime = Runtime::new();
-
-            runtime.eval(tokenizer.tokens, 1, vec![], vec![], true);
-        }
-        Subcommand::Play => {
+        }),
+        Subcommand::Play(opts) => with_stack(move || {
             let mut rl = Editor::<()>::new();
 
             println!("welcome to interactive mode \npress : Ctrl-C to exit");
-            let mut runtime = Runtime::new();
-            let mut i: usize = 1;
+            let mut resolver = Resolver::new().interactive();
+            let mut runtime = Runtime::new().with_max_depth(opts.max_depth.unwrap_or(MAX_DEPTH));
+            let mut sources = SourceMap::new();
             loop {
                 let readline = rl.readline("-> ");
                 match readline {
                     Ok(line) => {
                         rl.add_history_entry(line.as_str());
 
-                        let mut lexer = Tokenizer::new(&line);
-                        lexer.start();
-
-                        runtime.eval(lexer.tokens, i, vec![], vec![], false);
-
-                        i = i + 1;
+                        if let Err(errors) =
+                            play_line(&mut sources, &mut resolver, &mut runtime, &line)
+                        {
+                            report(&sources, &errors);
+                        }
                     }
                     Err(ReadlineError::Interrupted) => {
                         println!("^C");
@@ -96,6 +184,6 @@ fn main() {
                     }
                 }
             }
-        }
+        }),
     }
 }
@@ -1,73 +1,345 @@
+use crate::error::*;
+use crate::list::List;
 use crate::runtime::*;
+use crate::tokenizer::JOINT;
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{FromPrimitive, Zero};
 use rand::prelude::*;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::io::{stdin, stdout, Write};
+use std::rc::Rc;
 
-pub fn stdio(var: &Variable) {
+// what is left to print, the next piece last. values nest as deep as the
+// data a program builds, so printing one does not recurse
+enum Piece<'a> {
+    Text(&'a str),
+    Value(&'a Variable),
+    // strings inside a list or a record keep their quotes
+    Item(&'a Variable),
+}
+
+pub fn stdio(var: &Variable) -> TofResult<()> {
+    let mut pieces = vec![Piece::Value(var)];
+    while let Some(piece) = pieces.pop() {
+        match piece {
+            Piece::Text(text) => print!("{}", text),
+            Piece::Item(Variable::Str(string)) => print!("{:?}", string),
+            Piece::Item(var) | Piece::Value(var) => print_shallow(var, &mut pieces),
+        }
+    }
+    stdout().flush()?;
+    Ok(())
+}
+
+// prints `var` when it holds no other value, else leaves its pieces on
+// `pieces`
+fn print_shallow<'a>(var: &'a Variable, pieces: &mut Vec<Piece<'a>>) {
+    let mut parts = Vec::new();
     match var {
-        Variable::Lamda { args, value } => print!("args:{:#?} , value:{:#?}", args, value),
+        Variable::Lamda { args, value, .. } => print!("args:{:#?} , value:{:#?}", args, value),
+        Variable::Closure { function, .. } => {
+            print!("args:{:#?} , value:{:#?}", function.args, function.value)
+        }
         Variable::Rusty(_) => print!("a rusty function"),
+        Variable::Constructor(ctor) => {
+            print!("the constructor {} of {}", ctor.name, ctor.type_name)
+        }
+        // `Rect 1 2`, with parentheses around values that take arguments
+        Variable::Data(data) => {
+            parts.push(Piece::Text(&data.constructor.name));
+            for var in &data.values {
+                parts.push(Piece::Text(" "));
+                match var {
+                    Variable::Data(inner) if !inner.values.is_empty() => {
+                        parts.extend([Piece::Text("("), Piece::Value(var), Piece::Text(")")])
+                    }
+                    Variable::Int(_)
+                    | Variable::BigInt(_)
+                    | Variable::Float(_)
+                    | Variable::Rational(_)
+                        if var.to_float().is_some_and(|f| f < 0.0) =>
+                    {
+                        parts.extend([Piece::Text("("), Piece::Value(var), Piece::Text(")")])
+                    }
+                    _ => parts.push(Piece::Item(var)),
+                }
+            }
+        }
         Variable::Int(int) => print!("{}", int),
+        Variable::BigInt(int) => print!("{}", int),
+        // debug formatting keeps the `.0` of whole floats
+        Variable::Float(float) => print!("{:?}", float),
+        // `n/d`, or just `n` when the denominator is 1
+        Variable::Rational(ratio) => print!("{}", ratio),
         Variable::Str(string) => print!("{}", string),
         Variable::Bool(bool) => print!("{}", bool),
+        Variable::List(list) => {
+            parts.push(Piece::Text("["));
+            for (i, var) in list.iter().enumerate() {
+                if i > 0 {
+                    parts.push(Piece::Text(", "));
+                }
+                parts.push(Piece::Item(var));
+            }
+            parts.push(Piece::Text("]"));
+        }
+        Variable::Tuple(items) => {
+            parts.push(Piece::Text("("));
+            for (i, var) in items.iter().enumerate() {
+                if i > 0 {
+                    parts.push(Piece::Text(", "));
+                }
+                parts.push(Piece::Item(var));
+            }
+            // `(1,)`, so that it does not read as a plain `1`
+            if items.len() == 1 {
+                parts.push(Piece::Text(","));
+            }
+            parts.push(Piece::Text(")"));
+        }
+        Variable::Record(record) if record.is_empty() => print!("{{}}"),
+        Variable::Record(record) => {
+            parts.push(Piece::Text("{ "));
+            for (i, (name, var)) in record.iter().enumerate() {
+                if i > 0 {
+                    parts.push(Piece::Text(", "));
+                }
+                parts.extend([Piece::Text(name), Piece::Text(": "), Piece::Item(var)]);
+            }
+            parts.push(Piece::Text(" }"));
+        }
+    }
+    pieces.extend(parts.into_iter().rev());
+}
+
+fn arity(name: &str, args: &[Variable], expected: usize) -> TofResult<()> {
+    if args.len() != expected {
+        return Err(TofError::wrong_arity(name, expected, args.len()));
     }
-    stdout().flush().unwrap();
+    Ok(())
 }
 
-pub fn prelude(data: &mut Vars) {
+fn list<'a>(name: &str, var: &'a Variable) -> TofResult<&'a List> {
+    match var {
+        Variable::List(list) => Ok(list),
+        _ => Err(TofError::type_error(format!(
+            "{} takes a list, got {}",
+            name,
+            var.type_name()
+        ))),
+    }
+}
+
+fn empty(name: &str) -> TofError {
+    TofError::index(format!("{} of an empty list", name))
+        .with_help("check for `[]` before taking it apart")
+}
+
+// rounds toward zero, like `/` on two ints
+fn float_to_int(float: f64) -> TofResult<Variable> {
+    match BigInt::from_f64(float.trunc()) {
+        Some(int) => Ok(Variable::big(int)),
+        None => Err(TofError::arithmetic(format!(
+            "{:?} has no int value",
+            float
+        ))),
+    }
+}
+
+// builtins by name, the resolver and the runtime both number them in the
+// order of the map
+pub fn prelude(data: &mut BTreeMap<String, Variable>) {
     data.insert(
         "print".to_string(),
         Variable::Rusty(|args| {
             for var in args {
-                stdio(&var);
+                stdio(&var)?;
             }
-            stdio(&Variable::Str("\n".to_string()));
-            None
+            stdio(&Variable::Str("\n".to_string()))?;
+            Ok(None)
         }),
     );
 
     data.insert(
         "scan".to_string(),
         Variable::Rusty(|args| {
+            arity("scan", &args, 1)?;
             let mut string = String::new();
-            stdio(&args[0]);
+            stdio(&args[0])?;
 
-            stdin().read_line(&mut string).unwrap();
+            stdin().read_line(&mut string)?;
             string.pop();
-            Some(Variable::Str(string))
+            Ok(Some(Variable::Str(string)))
         }),
     );
 
     data.insert(
         "int".to_string(),
         Variable::Rusty(|args| {
+            arity("int", &args, 1)?;
             let int = match &args[0] {
-                Variable::Int(int) => int.clone(),
-                Variable::Str(string) => string.parse::<f64>().unwrap(),
-                _ => panic!("cannot parse"),
+                Variable::Int(_) | Variable::BigInt(_) => args[0].clone(),
+                Variable::Float(float) => float_to_int(*float)?,
+                Variable::Rational(ratio) => Variable::big(ratio.to_integer()),
+                Variable::Str(string) => match string.trim().parse::<BigInt>() {
+                    Ok(int) => Variable::big(int),
+                    Err(_) => match string.trim().parse::<f64>() {
+                        Ok(float) => float_to_int(float)?,
+                        Err(_) => {
+                            return Err(TofError::type_error(format!("cannot parse {:?}", string)));
+                        }
+                    },
+                },
+                _ => return Err(TofError::type_error("cannot parse".to_string())),
             };
-            Some(Variable::Int(int.clone()))
+            Ok(Some(int))
+        }),
+    );
+    data.insert(
+        "float".to_string(),
+        Variable::Rusty(|args| {
+            arity("float", &args, 1)?;
+            let float = match &args[0] {
+                Variable::Int(_)
+                | Variable::BigInt(_)
+                | Variable::Float(_)
+                | Variable::Rational(_) => args[0].to_float().unwrap(),
+                Variable::Str(string) => match string.trim().parse::<f64>() {
+                    Ok(float) => float,
+                    Err(_) => {
+                        return Err(TofError::type_error(format!("cannot parse {:?}", string)));
+                    }
+                },
+                _ => return Err(TofError::type_error("cannot parse".to_string())),
+            };
+            // like `1e400` in the source, numbers past the largest float are
+            // refused rather than turned into infinity
+            if !float.is_finite() && !matches!(args[0], Variable::Float(_)) {
+                return Err(TofError::arithmetic(
+                    "the number is too large for a float".to_string(),
+                ));
+            }
+            Ok(Some(Variable::Float(float)))
+        }),
+    );
+    data.insert(
+        "ratio".to_string(),
+        Variable::Rusty(|args| {
+            arity("ratio", &args, 2)?;
+            let ints = |var: &Variable| match var {
+                Variable::Int(int) => Some(BigInt::from(*int)),
+                Variable::BigInt(int) => Some(int.clone()),
+                _ => None,
+            };
+            match (ints(&args[0]), ints(&args[1])) {
+                (Some(_), Some(denom)) if denom.is_zero() => Err(TofError::arithmetic(
+                    "a ratio cannot have a zero denominator".to_string(),
+                )),
+                (Some(numer), Some(denom)) => {
+                    Ok(Some(Variable::Rational(BigRational::new(numer, denom))))
+                }
+                _ => Err(TofError::type_error(
+                    "ratio takes an int numerator and denominator".to_string(),
+                )),
+            }
         }),
     );
     data.insert(
         "len".to_string(),
         Variable::Rusty(|args| {
+            arity("len", &args, 1)?;
             let len = match &args[0] {
-                Variable::Str(str) => str,
-                _ => panic!("only give len of string"),
+                Variable::Str(str) => str.len(),
+                Variable::List(list) => list.len(),
+                Variable::Tuple(items) => items.len(),
+                _ => {
+                    return Err(TofError::type_error(
+                        "only give len of string, list or tuple".to_string(),
+                    ))
+                }
             };
-            let len = len.len();
-            Some(Variable::Int(len as f64))
+            Ok(Some(Variable::Int(len as i64)))
+        }),
+    );
+    data.insert(
+        "head".to_string(),
+        Variable::Rusty(|args| {
+            arity("head", &args, 1)?;
+            match list("head", &args[0])?.head() {
+                Some(var) => Ok(Some(var.clone())),
+                None => Err(empty("head")),
+            }
+        }),
+    );
+    data.insert(
+        "tail".to_string(),
+        Variable::Rusty(|args| {
+            arity("tail", &args, 1)?;
+            match list("tail", &args[0])?.tail() {
+                Some(list) => Ok(Some(Variable::List(list))),
+                None => Err(empty("tail")),
+            }
+        }),
+    );
+    data.insert(
+        "cons".to_string(),
+        Variable::Rusty(|args| {
+            arity("cons", &args, 2)?;
+            let list = list("cons", &args[1])?.cons(args[0].clone());
+            Ok(Some(Variable::List(list)))
+        }),
+    );
+    // `nth list n`, counting from 0
+    data.insert(
+        "nth".to_string(),
+        Variable::Rusty(|args| {
+            arity("nth", &args, 2)?;
+            let list = list("nth", &args[0])?;
+            let (index, shown) = match &args[1] {
+                Variable::Int(int) => (usize::try_from(*int).ok(), int.to_string()),
+                Variable::BigInt(int) => (None, int.to_string()),
+                _ => return Err(TofError::type_error("nth takes an int index".to_string())),
+            };
+            match index.and_then(|i| list.get(i)) {
+                Some(var) => Ok(Some(var.clone())),
+                None => Err(TofError::index(format!(
+                    "index {} is out of range for a list of length {}",
+                    shown,
+                    list.len()
+                ))),
+            }
+        }),
+    );
+    // `(a // b, a % b)`
+    data.insert(
+        "divmod".to_string(),
+        Variable::Rusty(|args| {
+            arity("divmod", &args, 2)?;
+            let quotient = Runtime::eval_op(JOINT::IDIV, args[0].clone(), args[1].clone())?;
+            let remainder = Runtime::eval_op(JOINT::MOD, args[0].clone(), args[1].clone())?;
+            Ok(Some(Variable::Tuple(Rc::new(Items(vec![
+                quotient, remainder,
+            ])))))
         }),
     );
     data.insert(
         "rand".to_string(),
-        Variable::Rusty(|_args| Some(Variable::Int(random::<f64>()))),
+        Variable::Rusty(|_args| Ok(Some(Variable::Float(random::<f64>())))),
     );
     data.insert(
         "round".to_string(),
-        Variable::Rusty(|args| match args[0] {
-            Variable::Int(i) => Some(Variable::Int(i.round() as f64)),
-            _ => panic!("only numbers please"),
+        Variable::Rusty(|args| {
+            arity("round", &args, 1)?;
+            match args[0] {
+                Variable::Int(_) | Variable::BigInt(_) => Ok(Some(args[0].clone())),
+                Variable::Float(f) => Ok(Some(float_to_int(f.round())?)),
+                Variable::Rational(ref ratio) => {
+                    Ok(Some(Variable::big(ratio.round().to_integer())))
+                }
+                _ => Err(TofError::type_error("only numbers please".to_string())),
+            }
         }),
     );
     data.insert(
@@ -75,8 +347,14 @@ pub fn prelude(data: &mut Vars) {
         Variable::Rusty(|args| {
             if args.len() == 1 {
                 match args[0] {
-                    Variable::Int(i) => std::process::exit(i as i32),
-                    _ => panic!("only numbers please"),
+                    Variable::Int(i) => match i32::try_from(i) {
+                        Ok(code) => std::process::exit(code),
+                        Err(_) => Err(TofError::arithmetic(format!(
+                            "{} is not a valid exit code",
+                            i
+                        ))),
+                    },
+                    _ => Err(TofError::type_error("only numbers please".to_string())),
                 }
             } else {
                 std::process::exit(100)
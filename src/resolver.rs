@@ -0,0 +1,285 @@
+use crate::error::*;
+use crate::prelude::*;
+use crate::span::Span;
+use crate::tokenizer::*;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::rc::Rc;
+
+// the names of one runtime frame and the slot each one lives in
+#[derive(Debug, Clone, Default)]
+struct Scope {
+    names: HashMap<String, usize>,
+    // names whose definition comes before the line being resolved
+    defined: HashSet<String>,
+    // the frame of a lamda call, code in it runs when the lamda is called
+    lamda: bool,
+}
+
+impl Scope {
+    fn declare(&mut self, name: &str) -> usize {
+        let slot = self.names.len();
+        *self.names.entry(name.to_string()).or_insert(slot)
+    }
+}
+
+// binds every name to the frame slot it is stored in, before anything runs.
+// the scopes mirror the frames the runtime creates : the prelude, the top
+// level, one for each lamda call and one for each `-> { }` scope.
+//
+// a name is visible from the line after its definition. lamdas only run
+// when called, so inside them the names of the enclosing blocks are visible
+// whatever their order, which lets lamdas call each other. before the
+// definition of `x`, an outer `x` is still visible
+pub struct Resolver {
+    scopes: Vec<Scope>,
+    errors: Vec<TofError>,
+    // in `tof play` a lamda may use a top level name a later line defines
+    interactive: bool,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        let mut data = BTreeMap::new();
+        prelude(&mut data);
+        let mut globals = Scope::default();
+        for name in data.keys() {
+            globals.declare(name);
+            globals.defined.insert(name.clone());
+        }
+        Resolver {
+            scopes: vec![globals, Scope::default()],
+            errors: Vec::new(),
+            interactive: false,
+        }
+    }
+
+    // names no line defines yet get a top level slot when used in a lamda,
+    // calling the lamda before a line defines them is a name error
+    pub fn interactive(mut self) -> Resolver {
+        self.interactive = true;
+        self
+    }
+
+    // resolves top level tokens, the names they define stay around for the
+    // next call which is what `tof play` relies on
+    pub fn start(&mut self, tokens: &mut [Tokens]) -> Result<(), Vec<TofError>> {
+        let top = self.scopes[1].clone();
+        self.block(tokens);
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            self.scopes[1] = top;
+            Err(std::mem::take(&mut self.errors))
+        }
+    }
+
+    fn current(&mut self) -> &mut Scope {
+        self.scopes.last_mut().unwrap()
+    }
+
+    fn block(&mut self, tokens: &mut [Tokens]) {
+        for line in tokens.iter() {
+            match &line.token {
+                Token::Def(def) => {
+                    self.current().declare(&def.name);
+                }
+                Token::Type(def) => {
+                    for ctor in &def.constructors {
+                        self.current().declare(&ctor.name);
+                    }
+                }
+                Token::Unpack(unpack) => {
+                    for name in &unpack.names {
+                        self.current().declare(name);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        for line in tokens.iter_mut() {
+            match &mut line.token {
+                Token::Def(def) => {
+                    def.slot = self.current().names[&def.name];
+                    self.expr(&mut def.value);
+                    self.current().defined.insert(def.name.clone());
+                }
+                Token::Unpack(unpack) => {
+                    unpack.slots = unpack
+                        .names
+                        .iter()
+                        .map(|name| self.current().names[name])
+                        .collect();
+                    self.expr(&mut unpack.value);
+                    for name in &unpack.names {
+                        self.current().defined.insert(name.clone());
+                    }
+                }
+                Token::Type(def) => {
+                    def.slots = def
+                        .constructors
+                        .iter()
+                        .map(|ctor| self.current().names[&ctor.name])
+                        .collect();
+                    for ctor in &def.constructors {
+                        self.current().defined.insert(ctor.name.clone());
+                    }
+                }
+                Token::Expr(expr) | Token::Return(expr) => self.expr(expr),
+            }
+        }
+    }
+
+    fn lookup(&mut self, name: &str, span: Span) -> Slot {
+        // whether the line runs later than the lines of the scope looked at
+        let mut later = false;
+        let mut defined_below = false;
+        for (depth, scope) in self.scopes.iter().rev().enumerate() {
+            if let Some(index) = scope.names.get(name) {
+                if later || scope.defined.contains(name) {
+                    return Slot {
+                        depth,
+                        index: *index,
+                    };
+                }
+                defined_below = true;
+            }
+            later |= scope.lamda;
+        }
+        if self.interactive && later && !defined_below {
+            let depth = self.scopes.len() - 2;
+            return Slot {
+                depth,
+                index: self.scopes[1].declare(name),
+            };
+        }
+        let err = if defined_below {
+            TofError::undefined(name)
+        } else {
+            TofError::name(format!("variable not in scope {}", name))
+                .with_help("define it with `name : value` before using it")
+        };
+        self.errors.push(err.at(span));
+        Slot::default()
+    }
+
+    fn expr(&mut self, expr: &mut Expr) {
+        let span = expr.span;
+        if let Err(err) = check_stack(span) {
+            // the expressions around this one nest as deep, one error is
+            // enough
+            if self
+                .errors
+                .iter()
+                .all(|err| err.kind != ErrorKind::Recursion)
+            {
+                self.errors.push(err);
+            }
+            return;
+        }
+        match &mut expr.kind {
+            ExprKind::Int(_)
+            | ExprKind::BigInt(_)
+            | ExprKind::Float(_)
+            | ExprKind::Rational(_)
+            | ExprKind::Str(_)
+            | ExprKind::Bool(_) => {}
+            ExprKind::Lamda(lamda) => {
+                let mut scope = Scope {
+                    lamda: true,
+                    ..Scope::default()
+                };
+                for arg in lamda.args.iter() {
+                    scope.declare(arg);
+                    scope.defined.insert(arg.clone());
+                }
+                self.scopes.push(scope);
+                self.block(Rc::make_mut(&mut lamda.value).as_mut_slice());
+                self.scopes.pop();
+            }
+            ExprKind::Scope(tokens) => {
+                self.scopes.push(Scope::default());
+                self.block(tokens);
+                self.scopes.pop();
+            }
+            ExprKind::Decision(decision) => self.decision(decision),
+            ExprKind::FcCall(call) => {
+                call.slot = self.lookup(&call.name, span);
+                for arg in call.args.iter_mut() {
+                    self.expr(arg);
+                }
+            }
+            ExprKind::Call(var) => var.slot = self.lookup(&var.name, span),
+            ExprKind::Op(op) => {
+                self.expr(&mut op.lhs);
+                self.expr(&mut op.rhs);
+            }
+            ExprKind::Not(expr) | ExprKind::Neg(expr) => self.expr(expr),
+            ExprKind::List(items) | ExprKind::Tuple(items) => {
+                for item in items.iter_mut() {
+                    self.expr(item);
+                }
+            }
+            ExprKind::Record(record) => {
+                if let Some(base) = &mut record.base {
+                    self.expr(base);
+                }
+                for (_, value) in record.fields.iter_mut() {
+                    self.expr(value);
+                }
+            }
+            ExprKind::Field(field) => self.expr(&mut field.record),
+            ExprKind::Match(m) => {
+                self.expr(&mut m.value);
+                for arm in m.arms.iter_mut() {
+                    self.scopes.push(Scope::default());
+                    self.pattern(&mut arm.pattern);
+                    self.block(&mut arm.body);
+                    self.scopes.pop();
+                }
+            }
+        }
+    }
+
+    // the names a pattern binds go in the frame of its arm
+    fn pattern(&mut self, pattern: &mut Pattern) {
+        let span = pattern.span;
+        match &mut pattern.kind {
+            PatternKind::Wildcard | PatternKind::Literal(_) => {}
+            PatternKind::Bind { name, slot } => {
+                *slot = self.current().declare(name);
+                self.current().defined.insert(name.clone());
+            }
+            PatternKind::List(items, rest) => {
+                for item in items.iter_mut().chain(rest.as_deref_mut()) {
+                    self.pattern(item);
+                }
+            }
+            PatternKind::Tuple(items) => {
+                for item in items.iter_mut() {
+                    self.pattern(item);
+                }
+            }
+            PatternKind::Record(fields) => {
+                for (_, field) in fields.iter_mut() {
+                    self.pattern(field);
+                }
+            }
+            PatternKind::Constructor(var, args) => {
+                var.slot = self.lookup(&var.name, span);
+                for arg in args.iter_mut() {
+                    self.pattern(arg);
+                }
+            }
+        }
+    }
+
+    // decision blocks run in the frame of the decision itself
+    fn decision(&mut self, decision: &mut Decision) {
+        self.expr(&mut decision.cond);
+        self.block(&mut decision.block);
+        if let Some(next) = &mut decision.next {
+            self.decision(next);
+        }
+    }
+}
@@ -1,311 +1,1700 @@
+use crate::compiler::Function;
+use crate::error::*;
+use crate::list::{Cons, List};
 use crate::prelude::*;
 use crate::tokenizer::*;
-use std::collections::{BTreeMap, VecDeque};
-use std::ops::Index;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
 
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Variable {
     Lamda {
-        args: Vec<String>,
-        value: Vec<Tokens>,
+        args: Rc<Vec<String>>,
+        value: Rc<Vec<Tokens>>,
+        // the scope the lamda was created in, so it keeps seeing it after
+        // that scope has returned
+        env: Vars,
     },
-    Rusty(fn(args: Vec<Variable>) -> Option<Variable>),
-    Int(f64),
+    // a lamda compiled for the vm
+    Closure {
+        function: Rc<Function>,
+        env: Vars,
+    },
+    Rusty(fn(args: Vec<Variable>) -> TofResult<Option<Variable>>),
+    // makes a `Data` from its arguments
+    Constructor(Rc<Constructor>),
+    Int(i64),
+    // only for ints that do not fit in an `Int`, see `Variable::big`
+    BigInt(BigInt),
+    Float(f64),
+    // always in lowest terms, which `BigRational` keeps it in
+    Rational(BigRational),
     Str(String),
     Bool(bool),
+    List(List),
+    Tuple(Rc<Items>),
+    // fields by name, updates copy the map
+    Record(Rc<Fields>),
+    Data(Rc<Data>),
+}
+
+// a value of a `type`, made by one of its constructors
+#[derive(Debug, PartialEq)]
+pub struct Data {
+    pub constructor: Rc<Constructor>,
+    pub values: Vec<Variable>,
+}
+
+// the items of a tuple
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Items(pub Vec<Variable>);
+
+// the fields of a record
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Fields(pub BTreeMap<String, Variable>);
+
+impl Deref for Items {
+    type Target = Vec<Variable>;
+
+    fn deref(&self) -> &Vec<Variable> {
+        &self.0
+    }
+}
+
+impl Deref for Fields {
+    type Target = BTreeMap<String, Variable>;
+
+    fn deref(&self) -> &BTreeMap<String, Variable> {
+        &self.0
+    }
+}
+
+// dropping a value drops the values it holds first, which recurses as deep
+// as the value nests : a list a million `Cons` long overflows the stack.
+// values nothing else refers to are emptied into `values` instead, so that
+// each one is dropped with nothing left in it
+pub fn drop_deep(mut values: Vec<Variable>) {
+    while let Some(mut var) = values.pop() {
+        match &mut var {
+            Variable::List(list) => list.take_items(&mut values),
+            Variable::Tuple(items) => {
+                if let Some(items) = Rc::get_mut(items) {
+                    values.append(&mut items.0);
+                }
+            }
+            Variable::Record(fields) => {
+                if let Some(fields) = Rc::get_mut(fields) {
+                    values.extend(std::mem::take(&mut fields.0).into_values());
+                }
+            }
+            Variable::Data(data) => {
+                if let Some(data) = Rc::get_mut(data) {
+                    values.append(&mut data.values);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Drop for Items {
+    fn drop(&mut self) {
+        drop_deep(std::mem::take(&mut self.0));
+    }
+}
+
+impl Drop for Fields {
+    fn drop(&mut self) {
+        drop_deep(std::mem::take(&mut self.0).into_values().collect());
+    }
+}
+
+impl Drop for Data {
+    fn drop(&mut self) {
+        drop_deep(std::mem::take(&mut self.values));
+    }
+}
+
+// each `type` declaration makes new constructors, they are told apart by
+// name
+fn same_constructor(a: &Constructor, b: &Constructor) -> bool {
+    a.name == b.name && a.type_name == b.type_name
+}
+
+// values nest as deep as the data a program builds, so `equals` and
+// `compare` keep the items left to check on a worklist instead of
+// recursing. the next one to check is last
+fn push_pairs<'a>(
+    pairs: &mut Vec<(&'a Variable, &'a Variable)>,
+    items: impl Iterator<Item = (&'a Variable, &'a Variable)>,
+) {
+    let start = pairs.len();
+    pairs.extend(items);
+    pairs[start..].reverse();
+}
+
+// what is left to do when ordering two sequences : an item to compare, or
+// the lengths once every item was equal, the shorter sequence first
+enum Order<'a> {
+    Items(&'a Variable, &'a Variable),
+    Lengths(usize, usize),
+}
+
+fn push_order<'a>(
+    steps: &mut Vec<Order<'a>>,
+    a: impl Iterator<Item = &'a Variable>,
+    b: impl Iterator<Item = &'a Variable>,
+    a_len: usize,
+    b_len: usize,
+) {
+    steps.push(Order::Lengths(a_len, b_len));
+    let start = steps.len();
+    steps.extend(a.zip(b).map(|(a, b)| Order::Items(a, b)));
+    steps[start..].reverse();
+}
+
+// the operands of an arithmetic or comparison operator, turned into the
+// wider of their two types : int < big int < rational < float
+enum Numbers {
+    Ints(i64, i64),
+    Bigs(BigInt, BigInt),
+    Rationals(BigRational, BigRational),
+    Floats(f64, f64),
+}
+
+impl Numbers {
+    fn new(lhs: &Variable, rhs: &Variable) -> Option<Numbers> {
+        let numbers = match (lhs, rhs) {
+            (Variable::Int(a), Variable::Int(b)) => Numbers::Ints(*a, *b),
+            (Variable::Float(_), _) | (_, Variable::Float(_)) => {
+                Numbers::Floats(lhs.to_float()?, rhs.to_float()?)
+            }
+            (Variable::Rational(_), _) | (_, Variable::Rational(_)) => {
+                Numbers::Rationals(lhs.to_rational()?, rhs.to_rational()?)
+            }
+            _ => Numbers::Bigs(lhs.to_big()?, rhs.to_big()?),
+        };
+        Some(numbers)
+    }
+
+    fn compare(&self) -> Option<Ordering> {
+        match self {
+            Numbers::Ints(a, b) => a.partial_cmp(b),
+            Numbers::Bigs(a, b) => a.partial_cmp(b),
+            Numbers::Rationals(a, b) => a.partial_cmp(b),
+            Numbers::Floats(a, b) => a.partial_cmp(b),
+        }
+    }
+}
+
+impl Variable {
+    // big ints only hold what does not fit in an int, so that every whole
+    // number has a single representation
+    pub fn big(int: BigInt) -> Variable {
+        match int.to_i64() {
+            Some(int) => Variable::Int(int),
+            None => Variable::BigInt(int),
+        }
+    }
+
+    // a constructor without fields is not a function but the one value it
+    // would make
+    pub fn constructor(ctor: &Rc<Constructor>) -> Variable {
+        if ctor.fields.is_empty() {
+            Variable::Data(Rc::new(Data {
+                constructor: ctor.clone(),
+                values: Vec::new(),
+            }))
+        } else {
+            Variable::Constructor(ctor.clone())
+        }
+    }
+
+    pub fn type_name(&self) -> &str {
+        match self {
+            Variable::Lamda { .. }
+            | Variable::Closure { .. }
+            | Variable::Rusty(_)
+            | Variable::Constructor(_) => "function",
+            Variable::Int(_) | Variable::BigInt(_) => "int",
+            Variable::Float(_) => "float",
+            Variable::Rational(_) => "rational",
+            Variable::Str(_) => "string",
+            Variable::Bool(_) => "bool",
+            Variable::List(_) => "list",
+            Variable::Tuple(_) => "tuple",
+            Variable::Record(_) => "record",
+            Variable::Data(data) => &data.constructor.type_name,
+        }
+    }
+
+    fn is_function(&self) -> bool {
+        self.type_name() == "function"
+    }
+
+    // `==` : numbers are equal by value whatever their type and values of
+    // different types are never equal. functions cannot be compared at all
+    pub fn equals(&self, other: &Variable) -> TofResult<bool> {
+        let mut pairs = vec![(self, other)];
+        while let Some((a, b)) = pairs.pop() {
+            if !a.equals_shallow(b, &mut pairs)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // compares what `self` and `other` hold directly and leaves their items
+    // on `pairs`
+    fn equals_shallow<'a>(
+        &'a self,
+        other: &'a Variable,
+        pairs: &mut Vec<(&'a Variable, &'a Variable)>,
+    ) -> TofResult<bool> {
+        if self.is_function() || other.is_function() {
+            return Err(
+                TofError::type_error("you cannot compare functions".to_string())
+                    .with_help("compare what they return instead"),
+            );
+        }
+        if let Some(numbers) = Numbers::new(self, other) {
+            return Ok(numbers.compare() == Some(Ordering::Equal));
+        }
+        match (self, other) {
+            (Variable::Str(a), Variable::Str(b)) => Ok(a == b),
+            (Variable::Bool(a), Variable::Bool(b)) => Ok(a == b),
+            (Variable::List(a), Variable::List(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                push_pairs(pairs, a.iter().zip(b.iter()));
+                Ok(true)
+            }
+            (Variable::Tuple(a), Variable::Tuple(b)) => {
+                if a.len() != b.len() {
+                    return Ok(false);
+                }
+                push_pairs(pairs, a.iter().zip(b.iter()));
+                Ok(true)
+            }
+            // values of the same type made by the same constructor, values
+            // of two types that happen to share names are never equal
+            (Variable::Data(a), Variable::Data(b)) => {
+                if !same_constructor(&a.constructor, &b.constructor)
+                    || a.values.len() != b.values.len()
+                {
+                    return Ok(false);
+                }
+                push_pairs(pairs, a.values.iter().zip(b.values.iter()));
+                Ok(true)
+            }
+            (Variable::Record(a), Variable::Record(b)) => {
+                if a.len() != b.len() || !a.keys().eq(b.keys()) {
+                    return Ok(false);
+                }
+                push_pairs(pairs, a.values().zip(b.values()));
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    // `<` and `>` : numbers by value, strings character by character and
+    // lists item by item. `None` when a float is NaN
+    pub fn compare(&self, other: &Variable) -> TofResult<Option<Ordering>> {
+        let mut steps = vec![Order::Items(self, other)];
+        while let Some(step) = steps.pop() {
+            let ordering = match step {
+                Order::Items(a, b) => a.compare_shallow(b, &mut steps)?,
+                Order::Lengths(a, b) => Some(a.cmp(&b)),
+            };
+            if ordering != Some(Ordering::Equal) {
+                return Ok(ordering);
+            }
+        }
+        Ok(Some(Ordering::Equal))
+    }
+
+    // orders what `self` and `other` hold directly and leaves their items on
+    // `steps`, a sequence is equal until its items were compared
+    fn compare_shallow<'a>(
+        &'a self,
+        other: &'a Variable,
+        steps: &mut Vec<Order<'a>>,
+    ) -> TofResult<Option<Ordering>> {
+        if let Some(numbers) = Numbers::new(self, other) {
+            return Ok(numbers.compare());
+        }
+        match (self, other) {
+            (Variable::Str(a), Variable::Str(b)) => Ok(Some(a.cmp(b))),
+            (Variable::List(a), Variable::List(b)) => {
+                push_order(steps, a.iter(), b.iter(), a.len(), b.len());
+                Ok(Some(Ordering::Equal))
+            }
+            (Variable::Tuple(a), Variable::Tuple(b)) => {
+                push_order(steps, a.iter(), b.iter(), a.len(), b.len());
+                Ok(Some(Ordering::Equal))
+            }
+            _ => Err(TofError::type_error(format!(
+                "you cannot order {} and {}",
+                self.type_name(),
+                other.type_name()
+            ))
+            .with_help("only numbers, strings, lists and tuples have an order")),
+        }
+    }
+
+    pub fn to_float(&self) -> Option<f64> {
+        match self {
+            Variable::Int(int) => Some(*int as f64),
+            Variable::BigInt(int) => int.to_f64(),
+            Variable::Float(float) => Some(*float),
+            Variable::Rational(ratio) => ratio.to_f64(),
+            _ => None,
+        }
+    }
+
+    fn to_rational(&self) -> Option<BigRational> {
+        match self {
+            Variable::Rational(ratio) => Some(ratio.clone()),
+            _ => self.to_big().map(BigRational::from_integer),
+        }
+    }
+
+    fn to_big(&self) -> Option<BigInt> {
+        match self {
+            Variable::Int(int) => Some(BigInt::from(*int)),
+            Variable::BigInt(int) => Some(int.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn division_by_zero() -> TofError {
+    TofError::arithmetic("division by zero".to_string())
+}
+
+// the most bits a power can have, bigger ones take too long to compute
+const MAX_POWER_BITS: u64 = 1 << 22;
+
+// `exp` is not negative. the size of the power is known before computing it
+fn power(a: &BigInt, exp: &BigInt) -> TofResult<BigInt> {
+    let too_large = || {
+        TofError::arithmetic(format!("the exponent {} is too large", exp))
+            .with_help(&format!("powers can have at most {} bits", MAX_POWER_BITS))
+    };
+    let exp = exp.to_u32().ok_or_else(too_large)?;
+    if a.bits().saturating_sub(1) * u64::from(exp) > MAX_POWER_BITS {
+        return Err(too_large());
+    }
+    Ok(a.pow(exp))
 }
 
-#[derive(Debug, Clone)]
+// `/` on two ints rounds toward zero, `//` rounds down and `%` takes the
+// sign of the divisor. ints that overflow are done again as big ints
+fn int_op(joint: JOINT, a: i64, b: i64) -> TofResult<Variable> {
+    let v = match joint {
+        JOINT::ADD => a.checked_add(b),
+        JOINT::SUB => a.checked_sub(b),
+        JOINT::MULT => a.checked_mul(b),
+        JOINT::DIV | JOINT::IDIV | JOINT::MOD if b == 0 => return Err(division_by_zero()),
+        JOINT::DIV => a.checked_div(b),
+        JOINT::IDIV => a.checked_div(b).map(|q| {
+            if a % b != 0 && (a < 0) != (b < 0) {
+                q - 1
+            } else {
+                q
+            }
+        }),
+        JOINT::MOD => a.checked_rem(b).map(|r| {
+            if r != 0 && (r < 0) != (b < 0) {
+                r + b
+            } else {
+                r
+            }
+        }),
+        JOINT::POW => u32::try_from(b).ok().and_then(|b| a.checked_pow(b)),
+        _ => unreachable!(),
+    };
+    match v {
+        Some(v) => Ok(Variable::Int(v)),
+        None => big_op(joint, BigInt::from(a), BigInt::from(b)),
+    }
+}
+
+fn big_op(joint: JOINT, a: BigInt, b: BigInt) -> TofResult<Variable> {
+    let v = match joint {
+        JOINT::ADD => a + b,
+        JOINT::SUB => a - b,
+        JOINT::MULT => a * b,
+        JOINT::DIV | JOINT::IDIV | JOINT::MOD if b.is_zero() => return Err(division_by_zero()),
+        JOINT::DIV => a / b,
+        JOINT::IDIV => a.div_floor(&b),
+        JOINT::MOD => a.mod_floor(&b),
+        // a negative power of an int is a fraction
+        JOINT::POW if b.is_negative() => {
+            return rational_op(joint, BigRational::from(a), BigRational::from(b))
+        }
+        JOINT::POW => power(&a, &b)?,
+        _ => unreachable!(),
+    };
+    Ok(Variable::big(v))
+}
+
+// `//` on fractions gives an int, a power stays exact as long as the
+// exponent is a whole number
+fn rational_op(joint: JOINT, a: BigRational, b: BigRational) -> TofResult<Variable> {
+    let v = match joint {
+        JOINT::ADD => a + b,
+        JOINT::SUB => a - b,
+        JOINT::MULT => a * b,
+        JOINT::DIV | JOINT::IDIV | JOINT::MOD if b.is_zero() => return Err(division_by_zero()),
+        JOINT::DIV => a / b,
+        JOINT::IDIV => return Ok(Variable::big((a / b).floor().to_integer())),
+        JOINT::MOD => {
+            let q = (&a / &b).floor();
+            a - b * q
+        }
+        JOINT::POW if !b.is_integer() => {
+            let (a, b) = (
+                a.to_f64().unwrap_or(f64::NAN),
+                b.to_f64().unwrap_or(f64::NAN),
+            );
+            return Ok(Variable::Float(float_op(joint, a, b)?));
+        }
+        JOINT::POW => {
+            if a.is_zero() && b.is_negative() {
+                return Err(division_by_zero());
+            }
+            let exp = b.to_integer().abs();
+            let power = BigRational::new(power(a.numer(), &exp)?, power(a.denom(), &exp)?);
+            if b.is_negative() {
+                power.recip()
+            } else {
+                power
+            }
+        }
+        _ => unreachable!(),
+    };
+    Ok(Variable::Rational(v))
+}
+
+fn float_op(joint: JOINT, a: f64, b: f64) -> TofResult<f64> {
+    let v = match joint {
+        JOINT::ADD => a + b,
+        JOINT::SUB => a - b,
+        JOINT::MULT => a * b,
+        JOINT::DIV | JOINT::IDIV | JOINT::MOD if b == 0.0 => return Err(division_by_zero()),
+        JOINT::DIV => a / b,
+        JOINT::IDIV => (a / b).floor(),
+        JOINT::MOD => a - b * (a / b).floor(),
+        JOINT::POW => a.powf(b),
+        _ => unreachable!(),
+    };
+    Ok(v)
+}
+
+struct Frame {
+    slots: RefCell<Vec<Option<Stored>>>,
+    parent: Option<Vars>,
+    // set once a value that can lead back to a frame is stored here, see
+    // `collect_cycles`
+    suspect: Cell<bool>,
+}
+
+// a lamda defined in a frame captures that frame, stored there as it is
+// the frame would own itself and never be freed : every call defining a
+// local helper would leak its frame. it is kept without the frame and gets
+// it back when read, which is always through that frame.
+//
+// a lamda reaching back to the frame holding it any other way, inside a
+// list or from a `-> { }` scope of the frame, makes a cycle that
+// `collect_cycles` frees
+#[derive(Clone)]
+enum Stored {
+    Value(Variable),
+    Lamda {
+        args: Rc<Vec<String>>,
+        value: Rc<Vec<Tokens>>,
+    },
+    Closure(Rc<Function>),
+}
+
+// a frame and the chain of frames enclosing it, cloning only clones the
+// reference so every clone sees the same variables. names are turned into
+// slots by the resolver before anything runs
+#[derive(Clone)]
 pub struct Vars {
-    vars: VecDeque<BTreeMap<String, Variable>>,
+    frame: Rc<Frame>,
 }
 
 impl Vars {
     pub fn new() -> Vars {
-        let mut list = VecDeque::new();
-        list.push_back(BTreeMap::new());
+        Vars::with_parent(None)
+    }
 
-        Vars { vars: list }
+    pub fn child(&self) -> Vars {
+        Vars::with_parent(Some(self.clone()))
     }
 
-    pub fn push(&mut self) {
-        self.vars.push_back(BTreeMap::new());
+    fn with_parent(parent: Option<Vars>) -> Vars {
+        if SUSPECTS.with(|suspects| suspects.borrow_mut().due()) {
+            collect_cycles();
+        }
+        Vars {
+            frame: Rc::new(Frame {
+                slots: RefCell::new(Vec::new()),
+                parent,
+                suspect: Cell::new(false),
+            }),
+        }
     }
 
-    pub fn pop(&mut self) {
-        self.vars.pop_back();
+    pub fn insert(&self, slot: usize, data: Variable) {
+        let stored = match data {
+            Variable::Lamda { args, value, env } if env == *self => Stored::Lamda { args, value },
+            Variable::Closure { function, env } if env == *self => Stored::Closure(function),
+            data => {
+                if !self.frame.suspect.get() && !data.edges().is_empty() {
+                    self.frame.suspect.set(true);
+                    SUSPECTS.with(|suspects| {
+                        suspects
+                            .borrow_mut()
+                            .frames
+                            .push(Rc::downgrade(&self.frame))
+                    });
+                }
+                Stored::Value(data)
+            }
+        };
+        let mut slots = self.frame.slots.borrow_mut();
+        if slots.len() <= slot {
+            slots.resize(slot + 1, None);
+        }
+        slots[slot] = Some(stored);
     }
 
-    pub fn insert(&mut self, id: String, data: Variable) {
-        let m = self.vars.back_mut().unwrap();
-        m.insert(id, data);
+    pub fn parent(&self) -> Option<Vars> {
+        self.frame.parent.clone()
     }
 
-    pub fn get(&mut self, id: &str, mut scope: usize) -> Option<(usize, &Variable)> {
-        let mut var = None;
+    // the top level frame, on top of the frame holding the prelude
+    pub fn globals() -> Vars {
+        let globals = Vars::new();
+        let mut data = BTreeMap::new();
+        prelude(&mut data);
+        for (slot, var) in data.into_values().enumerate() {
+            globals.insert(slot, var);
+        }
+        globals.child()
+    }
 
-        if let Some(val) = self.vars.back().unwrap().get(id) {
-            var = Some((scope, val))
-        } else {
-            while scope != 0 {
-                if let Some(val) = self.vars.index(scope).get(id) {
-                    var = Some((scope, val));
-                    break;
-                }
-                scope = scope - 1;
-                if scope == 0 {
-                    if let Some(val) = self.vars.index(scope).get(id) {
-                        var = Some((scope, val));
+    // `None` when the definition owning the slot has not run yet
+    pub fn get(&self, slot: Slot) -> Option<Variable> {
+        let mut vars = self;
+        for _ in 0..slot.depth {
+            vars = vars.frame.parent.as_ref()?;
+        }
+        let slots = vars.frame.slots.borrow();
+        let var = match slots.get(slot.index)?.as_ref()? {
+            Stored::Value(var) => var.clone(),
+            Stored::Lamda { args, value } => Variable::Lamda {
+                args: args.clone(),
+                value: value.clone(),
+                env: vars.clone(),
+            },
+            Stored::Closure(function) => Variable::Closure {
+                function: function.clone(),
+                env: vars.clone(),
+            },
+        };
+        Some(var)
+    }
+}
+
+// the frames holding a lamda, a list, a tuple, a record or a value of a type,
+// which are the only ways back to a frame
+struct Suspects {
+    frames: Vec<Weak<Frame>>,
+    // frames made since the last collection, and how many to wait for
+    made: usize,
+    wait: usize,
+}
+
+// a collection goes through everything reachable from the suspects. what it
+// frees was made since the last one, and waiting for as many new frames as
+// it kept keeps its cost per frame constant
+const COLLECT_EVERY: usize = 10_000;
+
+impl Suspects {
+    fn due(&mut self) -> bool {
+        self.made += 1;
+        self.made >= self.wait
+    }
+}
+
+thread_local! {
+    static SUSPECTS: RefCell<Suspects> = const {
+        RefCell::new(Suspects {
+            frames: Vec::new(),
+            made: 0,
+            wait: COLLECT_EVERY,
+        })
+    };
+}
+
+// something reference counted that a frame can be reached through
+enum Node {
+    Frame(Rc<Frame>),
+    Items(Rc<Items>),
+    Fields(Rc<Fields>),
+    Data(Rc<Data>),
+    Cons(Rc<Cons>),
+}
+
+impl Node {
+    fn key(&self) -> *const () {
+        match self {
+            Node::Frame(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Items(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Fields(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Data(rc) => Rc::as_ptr(rc) as *const (),
+            Node::Cons(rc) => Rc::as_ptr(rc) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Node::Frame(rc) => Rc::strong_count(rc),
+            Node::Items(rc) => Rc::strong_count(rc),
+            Node::Fields(rc) => Rc::strong_count(rc),
+            Node::Data(rc) => Rc::strong_count(rc),
+            Node::Cons(rc) => Rc::strong_count(rc),
+        }
+    }
+
+    // one node per reference held, `None` when the frame is being written
+    fn edges(&self) -> Option<Vec<Node>> {
+        let values: Vec<&Variable> = match self {
+            Node::Frame(frame) => {
+                let slots = frame.slots.try_borrow().ok()?;
+                let mut edges: Vec<Node> = frame
+                    .parent
+                    .iter()
+                    .map(|parent| Node::Frame(parent.frame.clone()))
+                    .collect();
+                for stored in slots.iter().flatten() {
+                    if let Stored::Value(var) = stored {
+                        edges.extend(var.edges());
                     }
-                    break;
                 }
+                return Some(edges);
+            }
+            Node::Items(items) => items.iter().collect(),
+            Node::Fields(fields) => fields.values().collect(),
+            Node::Data(data) => data.values.iter().collect(),
+            Node::Cons(cons) => {
+                let mut edges = cons.value().edges();
+                edges.extend(cons.next().first().cloned().map(Node::Cons));
+                return Some(edges);
             }
+        };
+        Some(values.into_iter().flat_map(Variable::edges).collect())
+    }
+}
+
+impl Variable {
+    fn edges(&self) -> Vec<Node> {
+        match self {
+            Variable::Lamda { env, .. } | Variable::Closure { env, .. } => {
+                vec![Node::Frame(env.frame.clone())]
+            }
+            Variable::List(list) => list.first().cloned().map(Node::Cons).into_iter().collect(),
+            Variable::Tuple(items) => vec![Node::Items(items.clone())],
+            Variable::Record(fields) => vec![Node::Fields(fields.clone())],
+            Variable::Data(data) => vec![Node::Data(data.clone())],
+            _ => Vec::new(),
         }
+    }
+}
+
+// frees the frames that only lamdas stored in them keep alive, by trial
+// deletion : every reference found going through what the suspect frames
+// reach is taken away from the count of what it points to. whatever is left
+// with references is kept alive from outside, along with all it reaches, and
+// the frames left over are emptied
+pub fn collect_cycles() {
+    let frames = SUSPECTS.with(|suspects| std::mem::take(&mut suspects.borrow_mut().frames));
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut index: HashMap<*const (), usize> = HashMap::new();
+    // references found to each node, and the nodes each one refers to
+    let mut found: Vec<usize> = Vec::new();
+    let mut edges: Vec<Option<Vec<usize>>> = Vec::new();
+    let mut add =
+        |node: Node, nodes: &mut Vec<Node>, found: &mut Vec<usize>| match index.get(&node.key()) {
+            Some(&at) => at,
+            None => {
+                index.insert(node.key(), nodes.len());
+                nodes.push(node);
+                found.push(0);
+                nodes.len() - 1
+            }
+        };
+    for frame in frames.iter().filter_map(Weak::upgrade) {
+        add(Node::Frame(frame), &mut nodes, &mut found);
+    }
+    let mut at = 0;
+    while at < nodes.len() {
+        let targets = nodes[at].edges().map(|targets| {
+            targets
+                .into_iter()
+                .map(|node| {
+                    let to = add(node, &mut nodes, &mut found);
+                    found[to] += 1;
+                    to
+                })
+                .collect()
+        });
+        edges.push(targets);
+        at += 1;
+    }
+
+    // `nodes` holds one reference to each node and every edge one more
+    let mut live = vec![false; nodes.len()];
+    let mut reach: Vec<usize> = (0..nodes.len())
+        .filter(|&at| nodes[at].strong_count() > 1 + found[at] || edges[at].is_none())
+        .collect();
+    while let Some(at) = reach.pop() {
+        if !live[at] {
+            live[at] = true;
+            reach.extend(edges[at].iter().flatten());
+        }
+    }
+
+    let mut emptied = Vec::new();
+    let mut kept = Vec::new();
+    for (at, node) in nodes.iter().enumerate() {
+        if let Node::Frame(frame) = node {
+            if !live[at] {
+                if let Ok(mut slots) = frame.slots.try_borrow_mut() {
+                    emptied.push(std::mem::take(&mut *slots));
+                }
+            } else if frame.suspect.get() {
+                kept.push(Rc::downgrade(frame));
+            }
+        }
+    }
+    let alive = live.iter().filter(|&&live| live).count();
+    drop(edges);
+    drop(nodes);
+    drop(emptied);
+    SUSPECTS.with(|suspects| {
+        let mut suspects = suspects.borrow_mut();
+        suspects.frames.append(&mut kept);
+        suspects.made = 0;
+        suspects.wait = alive.max(COLLECT_EVERY);
+    });
+}
 
-        var
+impl PartialEq for Vars {
+    fn eq(&self, other: &Vars) -> bool {
+        Rc::ptr_eq(&self.frame, &other.frame)
     }
+}
+
+// a frame can still hold a lamda that refers back to it, see `Stored`, so
+// the frame is not printed
+impl fmt::Debug for Vars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Vars({} slots)", self.frame.slots.borrow().len())
+    }
+}
+
+// what is left of a block once its last line is reached, the lamda called
+// there has not run yet
+enum Tail {
+    Done(Option<Variable>),
+    Call(Call),
+    // a call made for its effects, the block keeps the given value
+    Then(Call, Option<Variable>),
+}
 
-    pub fn scopes_number(&self) -> usize {
-        self.vars.len()
+struct Call {
+    site: CallSite,
+    body: Rc<Vec<Tokens>>,
+    env: Vars,
+}
+
+impl Tail {
+    fn worth(self, value: Option<Variable>) -> Tail {
+        match self {
+            Tail::Done(_) => Tail::Done(value),
+            Tail::Call(call) | Tail::Then(call, _) => Tail::Then(call, value),
+        }
     }
 }
 
+// how many calls, not counting calls in tail position, can be running at once
+pub const MAX_DEPTH: usize = 10_000;
+
+// every call outside of tail position and every nested expression nests
+// rust calls. when less than `STACK_RED_ZONE` of the stack is left,
+// evaluation goes on in a new `STACK_GROWTH` long segment, so only the depth
+// limit stops a program
+const STACK_GROWTH: usize = 16 * 1024 * 1024;
+
+pub fn recursion_error(max_depth: usize) -> TofError {
+    TofError::recursion(format!("maximum recursion depth of {} exceeded", max_depth)).with_help(
+        "make the recursive call the last thing the function does, or raise `--max-depth`",
+    )
+}
+
 pub struct Runtime {
     data: Vars,
+    // the calls being run, innermost last
+    calls: Vec<CallSite>,
+    max_depth: usize,
 }
 
 impl Runtime {
     pub fn new() -> Runtime {
-        let mut data: Vars = Vars::new();
-        prelude(&mut data);
-        Runtime { data }
+        Runtime {
+            data: Vars::globals(),
+            calls: Vec::new(),
+            max_depth: MAX_DEPTH,
+        }
     }
 
-    // pub fn start(lex: Vec<LEX>) -> Vars {
-    //     let mut data: Vars = Vars::new();
-    //     prelude(&mut data);
-    //     eval(lex, &mut data, 1, vec![], vec![]);
+    // set from `--max-depth` of `tof run` and `tof play`
+    pub fn with_max_depth(mut self, max_depth: usize) -> Runtime {
+        self.max_depth = max_depth;
+        self
+    }
 
-    //     data
-    // }
+    // runs top level tokens that went through `Resolver::start`, definitions
+    // stay around for the next call which is what `tof play` relies on
+    pub fn start(&mut self, tokens: Vec<Tokens>) -> TofResult<Option<Variable>> {
+        let env = self.data.clone();
+        let result = self.eval(&tokens, &env);
+        self.calls.clear();
+        result
+    }
 
-    pub fn eval(
-        &mut self,
-        tokens: Vec<Tokens>,
-        scope: usize,
-        args: Vec<Variable>,
-        args_t_s: Vec<String>,
-        drop: bool,
-    ) -> Option<Variable> {
-        let mut t = None;
-        self.data.push();
-        for (pos, e) in args_t_s.iter().enumerate() {
-            self.data.insert(e.to_string(), args[pos].clone());
+    // the tokens are only borrowed, a call never copies the body of the
+    // lamda it runs
+    pub fn eval(&mut self, tokens: &[Tokens], env: &Vars) -> TofResult<Option<Variable>> {
+        let tail = self.eval_block(tokens, env)?;
+        self.finish(tail)
+    }
+
+    // runs the calls a block left in tail position one after the other, so
+    // recursion as deep as the loop it stands for uses no rust stack
+    fn finish(&mut self, mut tail: Tail) -> TofResult<Option<Variable>> {
+        let depth = self.calls.len();
+        let mut worth = None;
+        loop {
+            let call = match tail {
+                Tail::Done(value) => {
+                    self.calls.truncate(depth);
+                    return Ok(worth.unwrap_or(value));
+                }
+                Tail::Call(call) => call,
+                Tail::Then(call, value) => {
+                    worth.get_or_insert(value);
+                    call
+                }
+            };
+            // a call in tail position takes the place of its caller
+            self.calls.truncate(depth);
+            if depth >= self.max_depth {
+                return Err(recursion_error(self.max_depth)
+                    .at(call.site.span)
+                    .with_trace(self.trace()));
+            }
+            self.calls.push(call.site);
+            tail = match self.eval_block(&call.body, &call.env) {
+                Ok(tail) => tail,
+                // the innermost call an error goes through sees the whole stack
+                Err(err) if err.trace.is_empty() => return Err(err.with_trace(self.trace())),
+                Err(err) => return Err(err),
+            };
         }
-        for line in tokens {
-            match line.token {
+    }
+
+    // the calls being run, innermost first
+    fn trace(&self) -> Vec<CallSite> {
+        self.calls.iter().rev().cloned().collect()
+    }
+
+    // the last line of a block is in tail position, a `~` gives the block its
+    // value and a plain expression leaves the value of the block as it is
+    fn eval_block(&mut self, tokens: &[Tokens], env: &Vars) -> TofResult<Tail> {
+        let mut t = None;
+        for (i, line) in tokens.iter().enumerate() {
+            let last = i == tokens.len() - 1;
+            match &line.token {
                 Token::Def(def) => {
-                    self.eval_def(def, scope);
+                    self.eval_def(def, env)?;
+                }
+                Token::Type(def) => {
+                    for (ctor, slot) in def.constructors.iter().zip(&def.slots) {
+                        env.insert(*slot, Variable::constructor(ctor));
+                    }
                 }
+                Token::Unpack(unpack) => {
+                    let value = self.eval_expr(&unpack.value, env)?;
+                    let items = Runtime::unpack(value, unpack.names.len())
+                        .map_err(|err| err.at(unpack.span))?;
+                    for (slot, item) in unpack.slots.iter().zip(items) {
+                        env.insert(*slot, item);
+                    }
+                }
+                Token::Expr(expr) if last => return Ok(self.eval_tail(expr, env)?.worth(t)),
                 Token::Expr(expr) => {
-                    self.eval_expr(expr, scope);
+                    self.eval_expr(expr, env)?;
                 }
+                Token::Return(expr) if last => return self.eval_tail(expr, env),
                 Token::Return(expr) => {
-                    t = self.eval_expr(expr, scope);
+                    t = self.eval_expr(expr, env)?;
                 }
             }
         }
-        if drop {
-            self.data.pop();
-        }
+        Ok(Tail::Done(t))
+    }
 
-        t
+    fn eval_tail(&mut self, expr: &Expr, env: &Vars) -> TofResult<Tail> {
+        let tail = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || match &expr.kind {
+            ExprKind::FcCall(call) => self.eval_call(call, expr, env),
+            ExprKind::Decision(decision) => self.eval_match(decision, env),
+            ExprKind::Match(m) => self.eval_arms(m, env),
+            ExprKind::Scope(tokens) => self.eval_block(tokens, &env.child()),
+            _ => self.eval_kind(expr, env).map(Tail::Done),
+        });
+        tail.map_err(|err| err.at(expr.span))
     }
 
-    pub fn eval_def(&mut self, def: Def, scope: usize) {
-        let val = match self.eval_expr(def.value, scope) {
+    pub fn eval_def(&mut self, def: &Def, env: &Vars) -> TofResult<()> {
+        let val = match self.eval_expr(&def.value, env)? {
             Some(val) => val,
             None => {
-                panic!("you cannot store void value");
+                return Err(TofError::void_definition(&def.name).at(def.span));
             }
         };
 
-        self.data.insert(def.name, val);
+        env.insert(def.slot, val);
+        Ok(())
     }
 
-    pub fn eval_expr(&mut self, expr: Box<Expr>, scope: usize) -> Option<Variable> {
-        let mut v = None;
+    pub fn eval_expr(&mut self, expr: &Expr, env: &Vars) -> TofResult<Option<Variable>> {
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || self.eval_kind(expr, env))
+            .map_err(|err| err.at(expr.span))
+    }
 
-        match *expr {
-            Expr::Int(int) => v = Some(Variable::Int(int)),
-            Expr::Str(string) => v = Some(Variable::Str(string.to_string())),
-            Expr::Lamda(Lamda { args, value }) => {
-                v = Some(Variable::Lamda {
-                    args: args.to_vec(),
-                    value: value.to_vec(),
-                })
+    fn eval_kind(&mut self, expr: &Expr, env: &Vars) -> TofResult<Option<Variable>> {
+        let v = match &expr.kind {
+            ExprKind::Int(int) => Some(Variable::Int(*int)),
+            ExprKind::BigInt(int) => Some(Variable::BigInt(int.clone())),
+            ExprKind::Float(float) => Some(Variable::Float(*float)),
+            ExprKind::Rational(ratio) => Some(Variable::Rational(ratio.clone())),
+            ExprKind::Str(string) => Some(Variable::Str(string.clone())),
+            ExprKind::Lamda(Lamda { args, value }) => Some(Variable::Lamda {
+                args: args.clone(),
+                value: value.clone(),
+                env: env.clone(),
+            }),
+            ExprKind::FcCall(call) => {
+                let tail = self.eval_call(call, expr, env)?;
+                self.finish(tail)?
+            }
+            ExprKind::Call(Var { name, slot }) => match env.get(*slot) {
+                Some(value) => Some(value),
+                None => return Err(TofError::undefined(name)),
+            },
+            ExprKind::Op(Op { joint, lhs, rhs }) if joint.short_circuits() => {
+                let lhs = self.eval_operand(lhs, env)?;
+                if Runtime::settles(*joint, &lhs)? {
+                    Some(lhs)
+                } else {
+                    let rhs = self.eval_operand(rhs, env)?;
+                    Some(Runtime::eval_op(*joint, lhs, rhs)?)
+                }
+            }
+            ExprKind::Op(Op { joint, lhs, rhs }) => {
+                let lhs = self.eval_operand(lhs, env)?;
+                let rhs = self.eval_operand(rhs, env)?;
+                Some(Runtime::eval_op(*joint, lhs, rhs)?)
+            }
+
+            ExprKind::Decision(mat) => {
+                let tail = self.eval_match(mat, env)?;
+                self.finish(tail)?
+            }
+            ExprKind::Match(m) => {
+                let tail = self.eval_arms(m, env)?;
+                self.finish(tail)?
+            }
+            ExprKind::Not(expr) => {
+                let var = self.eval_operand(expr, env)?;
+                Some(Variable::Bool(!Runtime::truth(&var, "not")?))
+            }
+            ExprKind::Neg(expr) => {
+                let var = self.eval_operand(expr, env)?;
+                Some(Runtime::eval_neg(var)?)
+            }
+            ExprKind::List(_) | ExprKind::Tuple(_) | ExprKind::Record(_) | ExprKind::Field(_) => {
+                Some(self.eval_compound(&expr.kind, env)?)
+            }
+            ExprKind::Bool(bool) => Some(Variable::Bool(*bool)),
+            ExprKind::Scope(s) => self.eval(s, &env.child())?,
+        };
+        Ok(v)
+    }
+
+    // lists, tuples and records, kept out of `eval_kind` so that its frame,
+    // which every nested call pays for, stays small
+    #[inline(never)]
+    fn eval_compound(&mut self, kind: &ExprKind, env: &Vars) -> TofResult<Variable> {
+        let v = match kind {
+            ExprKind::List(items) => {
+                Variable::List(self.eval_items(items, env, "list")?.into_iter().collect())
+            }
+            ExprKind::Tuple(items) => {
+                Variable::Tuple(Rc::new(Items(self.eval_items(items, env, "tuple")?)))
+            }
+            ExprKind::Record(record) => self.eval_record(record, env)?,
+            ExprKind::Field(Field { record, name }) => {
+                let record = self.eval_operand(record, env)?;
+                Runtime::field(record, name)?
+            }
+            _ => unreachable!(),
+        };
+        Ok(v)
+    }
+
+    // every item runs before a void one is reported, like on the vm
+    fn eval_items(&mut self, items: &[Expr], env: &Vars, kind: &str) -> TofResult<Vec<Variable>> {
+        let mut values = Vec::with_capacity(items.len());
+        for item in items {
+            values.push(self.eval_expr(item, env)?);
+        }
+        match values.into_iter().collect() {
+            Some(values) => Ok(values),
+            None => Err(TofError::void_item(kind)),
+        }
+    }
+
+    // the base and every field run before a void one is reported, like on
+    // the vm
+    fn eval_record(&mut self, record: &Record, env: &Vars) -> TofResult<Variable> {
+        let Record { base, fields } = record;
+        let base = match base {
+            Some(base) => Some(self.eval_expr(base, env)?),
+            None => None,
+        };
+        let mut values = Vec::with_capacity(fields.len());
+        for (name, value) in fields {
+            values.push((name, self.eval_expr(value, env)?));
+        }
+        let mut record = Vec::with_capacity(fields.len());
+        for (name, value) in values {
+            match value {
+                Some(value) => record.push((name.clone(), value)),
+                None => return Err(TofError::void_field(name)),
+            }
+        }
+        let base = match base {
+            Some(Some(base)) => Some(base),
+            Some(None) => return Err(TofError::void_operand()),
+            None => None,
+        };
+        Runtime::record(base, record)
+    }
+
+    // a lamda is not run here but handed back with the frame of the call
+    fn eval_call(&mut self, call: &FcCall, expr: &Expr, env: &Vars) -> TofResult<Tail> {
+        let FcCall { args, name, slot } = call;
+        let fc = match env.get(*slot) {
+            Some(v) => v,
+            None => return Err(TofError::undefined(name)),
+        };
+
+        let mut args_t_s = Vec::with_capacity(args.len());
+        for node in args {
+            match self.eval_expr(node, env)? {
+                Some(val) => args_t_s.push(val),
+                None => {
+                    return Err(TofError::void_argument(name));
+                }
             }
-            Expr::FcCall(FcCall { args, name }) => {
-                let mut tempd = self.data.clone();
-                let (fc_scope, fc) = match tempd.get(&name, scope) {
-                    Some(v) => v,
+        }
+
+        match fc {
+            Variable::Rusty(fnc) => Ok(Tail::Done(fnc(args_t_s)?)),
+            Variable::Constructor(ctor) => {
+                Ok(Tail::Done(Some(Runtime::construct(&ctor, args_t_s, name)?)))
+            }
+            Variable::Lamda { args, value, env } => {
+                if args.len() != args_t_s.len() {
+                    return Err(TofError::wrong_arity(name, args.len(), args_t_s.len()));
+                }
+                let call_env = env.child();
+                for (slot, val) in args_t_s.into_iter().enumerate() {
+                    call_env.insert(slot, val);
+                }
+                Ok(Tail::Call(Call {
+                    site: CallSite {
+                        name: name.clone(),
+                        span: expr.span,
+                    },
+                    body: value,
+                    env: call_env,
+                }))
+            }
+            _ => Err(TofError::type_error(format!("{} is not callable", name))),
+        }
+    }
+
+    pub fn construct(
+        ctor: &Rc<Constructor>,
+        values: Vec<Variable>,
+        name: &str,
+    ) -> TofResult<Variable> {
+        if ctor.fields.len() != values.len() {
+            return Err(TofError::wrong_arity(name, ctor.fields.len(), values.len())
+                .with_help(&format!("its fields are {}", ctor.fields.join(", "))));
+        }
+        Ok(Variable::Data(Rc::new(Data {
+            constructor: ctor.clone(),
+            values,
+        })))
+    }
+
+    fn eval_operand(&mut self, expr: &Expr, env: &Vars) -> TofResult<Variable> {
+        match self.eval_expr(expr, env)? {
+            Some(val) => Ok(val),
+            None => Err(TofError::void_operand()),
+        }
+    }
+
+    pub fn truth(var: &Variable, op: &str) -> TofResult<bool> {
+        match var {
+            Variable::Bool(bool) => Ok(*bool),
+            _ => Err(TofError::type_error(format!(
+                "you can only use booleans with `{}`",
+                op
+            ))),
+        }
+    }
+
+    // whether the left hand side of `and` / `or` is already the result :
+    // `FALSE` for `and`, `TRUE` for `or`
+    pub fn settles(joint: JOINT, lhs: &Variable) -> TofResult<bool> {
+        match joint {
+            JOINT::AND => Ok(!Runtime::truth(lhs, "and")?),
+            _ => Runtime::truth(lhs, "or"),
+        }
+    }
+
+    // `{ base | fields }` keeps the fields of the base it does not set, and
+    // can only set fields the base already has
+    pub fn record(base: Option<Variable>, fields: Vec<(String, Variable)>) -> TofResult<Variable> {
+        let mut record = match base {
+            None => BTreeMap::new(),
+            Some(Variable::Record(record)) => {
+                if let Some((name, _)) = fields.iter().find(|(name, _)| !record.contains_key(name))
+                {
+                    return Err(Runtime::no_field(name, &record)
+                        .with_help("an update only changes fields the record already has"));
+                }
+                record.0.clone()
+            }
+            Some(var) => {
+                return Err(TofError::type_error(format!(
+                    "you can only update a record, got {}",
+                    var.type_name()
+                )))
+            }
+        };
+        record.extend(fields);
+        Ok(Variable::Record(Rc::new(Fields(record))))
+    }
+
+    pub fn unpack(value: Option<Variable>, len: usize) -> TofResult<Vec<Variable>> {
+        match value {
+            Some(Variable::Tuple(items)) if items.len() == len => Ok(items.to_vec()),
+            Some(Variable::Tuple(items)) => Err(TofError::type_error(format!(
+                "cannot unpack a tuple of {} items into {} names",
+                items.len(),
+                len
+            ))),
+            Some(var) => Err(TofError::type_error(format!(
+                "you can only unpack a tuple, got {}",
+                var.type_name()
+            ))),
+            None => Err(
+                TofError::type_error("you cannot unpack void value".to_string()).with_help(
+                    "the right hand side must return a value, use `~` to return from a block",
+                ),
+            ),
+        }
+    }
+
+    pub fn field(record: Variable, name: &str) -> TofResult<Variable> {
+        match record {
+            Variable::Data(data) => {
+                let ctor = &data.constructor;
+                match ctor.fields.iter().position(|field| field == name) {
+                    Some(i) => Ok(data.values[i].clone()),
                     None => {
-                        panic!("varible not found {}", name);
+                        let help = match ctor.fields.len() {
+                            0 => format!("{} has no fields", ctor.name),
+                            _ => format!("its fields are {}", ctor.fields.join(", ")),
+                        };
+                        Err(
+                            TofError::name(format!("no field `{}` in {}", name, ctor.name))
+                                .with_help(&help),
+                        )
                     }
+                }
+            }
+            Variable::Tuple(items) => {
+                let index = name.parse::<usize>().map_err(|_| {
+                    TofError::type_error(format!("a tuple has no field `{}`", name))
+                        .with_help("read the items of a tuple by position : `pair.0`")
+                })?;
+                match items.get(index) {
+                    Some(var) => Ok(var.clone()),
+                    None => Err(TofError::index(format!(
+                        "index {} is out of range for a tuple of length {}",
+                        index,
+                        items.len()
+                    ))),
+                }
+            }
+            Variable::Record(record) => match record.get(name) {
+                Some(var) => Ok(var.clone()),
+                None => Err(Runtime::no_field(name, &record)),
+            },
+            var => Err(TofError::type_error(format!(
+                "only records have fields, got {}",
+                var.type_name()
+            ))),
+        }
+    }
+
+    fn no_field(name: &str, record: &BTreeMap<String, Variable>) -> TofError {
+        let fields: Vec<&str> = record.keys().map(String::as_str).collect();
+        let help = match fields.len() {
+            0 => "the record has no fields".to_string(),
+            _ => format!("its fields are {}", fields.join(", ")),
+        };
+        TofError::name(format!("no field `{}` in this record", name)).with_help(&help)
+    }
+
+    pub fn eval_neg(var: Variable) -> TofResult<Variable> {
+        let v = match var {
+            Variable::Int(int) => match int.checked_neg() {
+                Some(int) => Variable::Int(int),
+                None => Variable::big(-BigInt::from(int)),
+            },
+            Variable::BigInt(int) => Variable::big(-int),
+            Variable::Float(float) => Variable::Float(-float),
+            Variable::Rational(ratio) => Variable::Rational(-ratio),
+            _ => {
+                return Err(TofError::type_error(
+                    "you can only negate numbers".to_string(),
+                ))
+            }
+        };
+        Ok(v)
+    }
+
+    pub fn eval_op(joint: JOINT, lhs: Variable, rhs: Variable) -> TofResult<Variable> {
+        let numbers = Numbers::new(&lhs, &rhs);
+        let v = match joint {
+            JOINT::ADD
+            | JOINT::SUB
+            | JOINT::MULT
+            | JOINT::DIV
+            | JOINT::IDIV
+            | JOINT::MOD
+            | JOINT::POW => match (numbers, lhs, rhs) {
+                (Some(Numbers::Ints(a, b)), _, _) => int_op(joint, a, b)?,
+                (Some(Numbers::Bigs(a, b)), _, _) => big_op(joint, a, b)?,
+                (Some(Numbers::Rationals(a, b)), _, _) => rational_op(joint, a, b)?,
+                (Some(Numbers::Floats(a, b)), _, _) => Variable::Float(float_op(joint, a, b)?),
+                (None, Variable::Str(string), Variable::Str(string2)) if joint == JOINT::ADD => {
+                    Variable::Str(string + &string2)
+                }
+                _ => {
+                    let message = match joint {
+                        JOINT::ADD => "you can only add numbers and string",
+                        JOINT::SUB => "you can only subtract numbers",
+                        JOINT::MULT => "you can only multiply numbers",
+                        JOINT::MOD => "you can only take the remainder of numbers",
+                        JOINT::POW => "you can only raise numbers to a power",
+                        _ => "you can only divide numbers",
+                    };
+                    return Err(TofError::type_error(message.to_string()));
+                }
+            },
+            JOINT::EQU | JOINT::NOT => Variable::Bool(lhs.equals(&rhs)? == (joint == JOINT::EQU)),
+            JOINT::GREAT | JOINT::LESS | JOINT::GREATEQ | JOINT::LESSEQ => {
+                let ordering = lhs.compare(&rhs)?;
+                let holds = match (joint, ordering) {
+                    (_, None) => false,
+                    (JOINT::GREAT, Some(ordering)) => ordering == Ordering::Greater,
+                    (JOINT::LESS, Some(ordering)) => ordering == Ordering::Less,
+                    (JOINT::GREATEQ, Some(ordering)) => ordering != Ordering::Less,
+                    (_, Some(ordering)) => ordering != Ordering::Greater,
                 };
+                Variable::Bool(holds)
+            }
+            JOINT::AND => {
+                Variable::Bool(Runtime::truth(&lhs, "and")? && Runtime::truth(&rhs, "and")?)
+            }
+            JOINT::OR => Variable::Bool(Runtime::truth(&lhs, "or")? || Runtime::truth(&rhs, "or")?),
+        };
+        Ok(v)
+    }
 
-                let args_t_s = args
-                    .iter()
-                    .map(|node| match self.eval_expr(node.clone(), scope) {
-                        Some(val) => val,
-                        None => {
-                            panic!("sorry you cannot pass void value");
-                        }
-                    })
-                    .collect();
+    // the first arm whose pattern fits runs, in a frame holding what the
+    // pattern bound
+    fn eval_arms(&mut self, m: &Match, env: &Vars) -> TofResult<Tail> {
+        let value = match self.eval_expr(&m.value, env)? {
+            Some(value) => value,
+            None => return Err(TofError::void_match().at(m.value.span)),
+        };
+        for arm in &m.arms {
+            let scope = env.child();
+            if Runtime::matches(&arm.pattern, &value, &scope)? {
+                return self.eval_block(&arm.body, &scope);
+            }
+        }
+        Err(TofError::no_match(value.type_name()).at(m.value.span))
+    }
 
-                match fc {
-                    Variable::Rusty(fnc) => v = fnc(args_t_s),
-                    Variable::Lamda { args, value } => {
-                        v = self.eval(value.to_vec(), fc_scope, args_t_s, args.clone(), true)
-                    }
-                    _ => {
-                        panic!("not callable");
+    // binds the names of `pattern` in `scope` as it goes, a pattern that
+    // does not fit may leave some of them bound
+    pub fn matches(pattern: &Pattern, value: &Variable, scope: &Vars) -> TofResult<bool> {
+        let fits = match (&pattern.kind, value) {
+            (PatternKind::Wildcard, _) => true,
+            (PatternKind::Bind { slot, .. }, _) => {
+                scope.insert(*slot, value.clone());
+                true
+            }
+            (PatternKind::Literal(kind), _) => {
+                !value.is_function() && Runtime::literal(kind).equals(value)?
+            }
+            (PatternKind::Tuple(items), Variable::Tuple(values)) => {
+                items.len() == values.len() && Runtime::matches_all(items, values.iter(), scope)?
+            }
+            (PatternKind::List(items, rest), Variable::List(list)) => {
+                let len = items.len();
+                let fits_len = list.len() == len || (rest.is_some() && list.len() > len);
+                if !fits_len || !Runtime::matches_all(items, list.iter(), scope)? {
+                    false
+                } else {
+                    match rest {
+                        Some(rest) => {
+                            let mut left = list.clone();
+                            for _ in 0..len {
+                                left = left.tail().unwrap();
+                            }
+                            Runtime::matches(rest, &Variable::List(left), scope)?
+                        }
+                        None => true,
                     }
                 }
             }
-            Expr::Call(name) => {
-                v = match self.data.get(&name, scope) {
-                    Some(value) => Some(value.1.clone()),
-                    None => {
-                        panic!("variable not in scope {}", name);
+            (PatternKind::Record(fields), Variable::Record(record)) => {
+                for (name, field) in fields {
+                    match record.get(name) {
+                        Some(var) if Runtime::matches(field, var, scope)? => {}
+                        _ => return Ok(false),
                     }
-                };
+                }
+                true
             }
-            Expr::Op(Op { joint, lhs, rhs }) => {
-                if let Some(lhs) = self.eval_expr(lhs, scope) {
-                    if let Some(rhs) = self.eval_expr(rhs, scope) {
-                        match joint {
-                            JOINT::ADD => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Int(int + int2));
-                                    }
-                                    _ => panic!("you can only add numbers and string"),
-                                },
-                                Variable::Str(string) => match rhs {
-                                    Variable::Str(string2) => {
-                                        v = Some(Variable::Str(string + &string2));
-                                    }
-                                    _ => panic!("you can only add numbers and string"),
-                                },
-                                _ => panic!("you can only add numsbers and string"),
-                            },
-                            JOINT::SUB => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Int(int - int2));
-                                    }
-                                    _ => panic!("you can only subtract numbers"),
-                                },
-                                _ => panic!("you can only subtract numbers"),
-                            },
-                            JOINT::MULT => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Int(int * int2));
-                                    }
-                                    _ => panic!("you can only multiply numbers"),
-                                },
-                                _ => panic!("you can only multiply numbers"),
-                            },
-                            JOINT::DIV => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Int(int / int2));
-                                    }
-                                    _ => panic!("you can only divide numbers"),
-                                },
-                                _ => panic!("you can only divide numbers"),
-                            },
-                            JOINT::EQU => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Bool(int == int2));
-                                    }
-                                    _ => panic!("you can only compare string and number"),
-                                },
-                                Variable::Str(string) => match rhs {
-                                    Variable::Str(string2) => {
-                                        v = Some(Variable::Bool(string == string2));
-                                    }
-                                    _ => panic!("you can only add numbers and string"),
-                                },
-                                _ => panic!("you can only compare string and number"),
-                            },
-                            JOINT::GREAT => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Bool(int > int2));
-                                    }
-                                    _ => panic!("you can only compare string and number"),
-                                },
-                                _ => {
-                                    panic!("only numbers are allowed");
-                                }
-                            },
-                            JOINT::LESS => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Bool(int < int2));
-                                    }
-                                    _ => panic!("you can only compare string and number"),
-                                },
-                                _ => {
-                                    panic!("only numbers are allowed");
-                                }
-                            },
-                            JOINT::NOT => match lhs {
-                                Variable::Int(int) => match rhs {
-                                    Variable::Int(int2) => {
-                                        v = Some(Variable::Bool(int != int2));
-                                    }
-                                    _ => panic!("you can only compare string and number"),
-                                },
-                                Variable::Str(string) => match rhs {
-                                    Variable::Str(string2) => {
-                                        v = Some(Variable::Bool(string != string2));
-                                    }
-                                    _ => panic!("you can only add numbers and string"),
-                                },
-                                _ => panic!("you can only compare string and number"),
-                            },
-                        }
+            (PatternKind::Constructor(var, args), _) => {
+                let ctor = Runtime::pattern_constructor(var, args.len(), scope)
+                    .map_err(|err| err.at(pattern.span))?;
+                match value {
+                    Variable::Data(data) if same_constructor(&ctor, &data.constructor) => {
+                        Runtime::matches_all(args, data.values.iter(), scope)?
                     }
-                };
+                    _ => false,
+                }
             }
+            _ => false,
+        };
+        Ok(fits)
+    }
 
-            Expr::Decision(mat) => {
-                v = self.eval_match(mat, scope);
+    fn matches_all<'a>(
+        patterns: &[Pattern],
+        values: impl Iterator<Item = &'a Variable>,
+        scope: &Vars,
+    ) -> TofResult<bool> {
+        for (pattern, value) in patterns.iter().zip(values) {
+            if !Runtime::matches(pattern, value, scope)? {
+                return Ok(false);
             }
-            Expr::Bool(bool) => {
-                v = Some(Variable::Bool(bool));
-            }
-            Expr::Scope(s) => {
-                v = self.eval(s, self.data.scopes_number(), vec![], vec![], true);
+        }
+        Ok(true)
+    }
+
+    // the constructor a pattern names, given as many patterns as it has
+    // fields
+    fn pattern_constructor(var: &Var, args: usize, scope: &Vars) -> TofResult<Rc<Constructor>> {
+        let ctor = match scope.get(var.slot) {
+            Some(Variable::Constructor(ctor)) => ctor,
+            Some(Variable::Data(data)) if data.values.is_empty() => data.constructor.clone(),
+            Some(_) => {
+                return Err(
+                    TofError::type_error(format!("{} is not a constructor", var.name))
+                        .with_help("declare it with `type Name = Constructor field ...`"),
+                )
             }
+            None => return Err(TofError::undefined(&var.name)),
+        };
+        if ctor.fields.len() != args {
+            let help = match ctor.fields.len() {
+                0 => format!("{} has no fields", ctor.name),
+                _ => format!("its fields are {}", ctor.fields.join(", ")),
+            };
+            return Err(TofError::wrong_arity(&var.name, ctor.fields.len(), args).with_help(&help));
+        }
+        Ok(ctor)
+    }
+
+    fn literal(kind: &ExprKind) -> Variable {
+        match kind {
+            ExprKind::Int(int) => Variable::Int(*int),
+            ExprKind::BigInt(int) => Variable::BigInt(int.clone()),
+            ExprKind::Float(float) => Variable::Float(*float),
+            ExprKind::Rational(ratio) => Variable::Rational(ratio.clone()),
+            ExprKind::Str(string) => Variable::Str(string.clone()),
+            ExprKind::Bool(bool) => Variable::Bool(*bool),
+            _ => unreachable!(),
         }
-        v
     }
 
-    pub fn eval_match(&mut self, m: Decision, scope: usize) -> Option<Variable> {
-        if self.eval_expr(m.cond, scope) == Some(Variable::Bool(true)) {
-            self.eval(m.block, self.data.scopes_number(), vec![], vec![], true)
+    fn eval_match(&mut self, m: &Decision, env: &Vars) -> TofResult<Tail> {
+        if self.eval_expr(&m.cond, env)? == Some(Variable::Bool(true)) {
+            self.eval_block(&m.block, env)
         } else {
-            match m.next {
-                Some(m) => self.eval_match(*m, scope),
-                None => None,
+            match &m.next {
+                Some(m) => self.eval_match(m, env),
+                None => Ok(Tail::Done(None)),
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Resolver;
+    use crate::tokenizer::Tokenizer;
+    use crate::vm::Vm;
+    use std::thread;
+
+    fn parse(src: &str) -> Vec<Tokens> {
+        let mut tokenizer = Tokenizer::new(src, 0);
+        tokenizer.start().expect("the program parses");
+        Resolver::new()
+            .start(&mut tokenizer.tokens)
+            .expect("the program resolves");
+        tokenizer.tokens
+    }
+
+    fn run(src: &str) -> TofResult<Option<Variable>> {
+        Runtime::new().start(parse(src))
+    }
+
+    // runs `src` on both engines, it returns a lamda, and gives the frame
+    // `up` frames above the one the lamda was made in, after a collection
+    fn frames_left(src: &str, up: usize) -> Vec<Weak<Frame>> {
+        let values = vec![run(src), Vm::new().start(&parse(src))];
+        let frames = values
+            .into_iter()
+            .map(|value| match value.unwrap().unwrap() {
+                Variable::Lamda { env, .. } | Variable::Closure { env, .. } => {
+                    let mut env = env;
+                    for _ in 0..up {
+                        env = env.parent().unwrap();
+                    }
+                    Rc::downgrade(&env.frame)
+                }
+                other => panic!("expected a lamda, got {:?}", other),
+            })
+            .collect();
+        collect_cycles();
+        frames
+    }
+
+    // the frame of a call that defines a helper used to own itself through
+    // the helper, and leaked
+    #[test]
+    fn local_lamdas_do_not_keep_their_frame() {
+        let src = "f : n -> {\n  helper : x -> x + n\n  ~ helper\n}\n~ f 1\n";
+        for frame in frames_left(src, 0) {
+            assert!(frame.upgrade().is_none(), "the call frame leaked");
+        }
+    }
+
+    #[test]
+    fn lamdas_kept_in_values_do_not_keep_their_frame() {
+        let src = "\
+f : n -> {
+  xs : [x -> x + n]
+  t : (x -> x * n, 1)
+  r : { f: x -> x - n }
+  ~ r.f
+}
+~ f 1
+";
+        for frame in frames_left(src, 0) {
+            assert!(frame.upgrade().is_none(), "the call frame leaked");
+        }
+    }
+
+    #[test]
+    fn lamdas_of_inner_scopes_do_not_keep_their_frame() {
+        let src = "\
+f : n -> {
+  g : -> {
+    h : x -> x + n
+    ~ h
+  }
+  m : match n { k -> y -> y + k }
+  ~ g
+}
+~ f 1
+";
+        for frame in frames_left(src, 1) {
+            assert!(frame.upgrade().is_none(), "the call frame leaked");
+        }
+    }
+
+    // the frames `spin` makes run a few collections while `r` is kept
+    #[test]
+    fn values_holding_lamdas_outlive_their_call() {
+        let src = "\
+mk : n -> {
+  r : { f: x -> x + n }
+  ~ r
+}
+r : mk 1
+spin : i -> (i == 0) ? 0 || spin (i - 1)
+s : spin 30000
+g : r.f
+~ g 2
+";
+        assert_eq!(run(src).unwrap(), Some(Variable::Int(3)));
+        assert_eq!(
+            Vm::new().start(&parse(src)).unwrap(),
+            Some(Variable::Int(3))
+        );
+    }
+
+    #[test]
+    fn int_powers_stay_ints() {
+        let power = Runtime::eval_op(JOINT::POW, Variable::Int(2), Variable::Int(64)).unwrap();
+        assert_eq!(power, Variable::big(BigInt::from(1u8) << 64));
+        let third = Runtime::eval_op(JOINT::DIV, power, Variable::Int(3)).unwrap();
+        assert_eq!(third, Variable::Int(6148914691236517205));
+    }
+
+    #[test]
+    fn powers_too_large_to_compute_are_refused() {
+        let bits = MAX_POWER_BITS as i64;
+        let power = Runtime::eval_op(JOINT::POW, Variable::Int(2), Variable::Int(bits)).unwrap();
+        assert_eq!(power, Variable::big(BigInt::from(1u8) << bits));
+        for exp in [bits + 1, 3_000_000_000] {
+            let err = Runtime::eval_op(JOINT::POW, Variable::Int(2), Variable::Int(exp));
+            assert_eq!(err.unwrap_err().kind, ErrorKind::Arithmetic);
+        }
+    }
+
+    // calls with the recursive call nested deep enough to outgrow the stack
+    // of the thread long before reaching the depth limit
+    #[test]
+    fn deep_nesting_grows_the_stack() {
+        let mut call = "deep (n - 1)".to_string();
+        for _ in 0..10 {
+            call = format!("(1 + {})", call);
+        }
+        let def = format!("deep : n -> (n == 0) ? 0 || {}\n", call);
+        let deep = move |n: usize| {
+            let src = format!("{}~ deep {}\n", def, n);
+            Runtime::new().with_max_depth(1000).start(parse(&src))
+        };
+        thread::Builder::new()
+            .stack_size(2 * 1024 * 1024)
+            .spawn(move || {
+                assert_eq!(deep(999).unwrap(), Some(Variable::Int(9990)));
+                assert_eq!(deep(1001).unwrap_err().kind, ErrorKind::Recursion);
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    }
+
+    // each value holds the one made before it, 200000 deep
+    #[test]
+    fn deep_values_drop_without_recursing() {
+        let nests: [fn(Variable) -> Variable; 4] = [
+            |var| Variable::List(vec![var, Variable::Int(1)].into_iter().collect()),
+            |var| Variable::Tuple(Rc::new(Items(vec![var, Variable::Int(1)]))),
+            |var| {
+                let fields = BTreeMap::from([("next".to_string(), var)]);
+                Variable::Record(Rc::new(Fields(fields)))
+            },
+            |var| {
+                let constructor = Rc::new(Constructor {
+                    type_name: "L".to_string(),
+                    name: "Cons".to_string(),
+                    fields: vec!["h".to_string(), "t".to_string()],
+                });
+                Variable::Data(Rc::new(Data {
+                    constructor,
+                    values: vec![Variable::Int(1), var],
+                }))
+            },
+        ];
+        for nest in nests {
+            let mut var = Variable::Int(0);
+            for _ in 0..200_000 {
+                var = nest(var);
+            }
+            drop(var);
+        }
+    }
+
+    #[test]
+    fn deep_values_compare_without_recursing() {
+        let nest = || {
+            let mut var = Variable::Int(0);
+            for n in 0..200_000 {
+                let list = vec![var, Variable::Int(n)].into_iter().collect();
+                var = Variable::Tuple(Rc::new(Items(vec![Variable::List(list)])));
+            }
+            var
+        };
+        let (a, b) = (nest(), nest());
+        assert!(a.equals(&b).unwrap());
+        assert_eq!(a.compare(&b).unwrap(), Some(Ordering::Equal));
+    }
+}
@@ -0,0 +1,87 @@
+pub type FileId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Span {
+    pub file: FileId,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(file: FileId, start: usize, end: usize) -> Span {
+        Span { file, start, end }
+    }
+
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            file: self.file,
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub src: String,
+    line_starts: Vec<usize>,
+}
+
+impl SourceFile {
+    pub fn new(name: &str, src: &str) -> SourceFile {
+        let mut line_starts = vec![0];
+        for (i, c) in src.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        SourceFile {
+            name: name.to_string(),
+            src: src.to_string(),
+            line_starts,
+        }
+    }
+
+    // 1-based line and column of a byte offset, column counted in chars
+    pub fn line_col(&self, offset: usize) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let start = self.line_starts[line];
+        let end = offset.min(self.src.len());
+        let col = self.src[start..end].chars().count() + 1;
+        (line + 1, col)
+    }
+
+    // text of a 1-based line without its newline
+    pub fn line(&self, line: usize) -> &str {
+        let start = self.line_starts[line - 1];
+        let end = match self.line_starts.get(line) {
+            Some(next) => next - 1,
+            None => self.src.len(),
+        };
+        self.src[start..end].trim_end_matches('\r')
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> SourceMap {
+        SourceMap { files: Vec::new() }
+    }
+
+    pub fn add(&mut self, name: &str, src: &str) -> FileId {
+        self.files.push(SourceFile::new(name, src));
+        self.files.len() - 1
+    }
+
+    pub fn get(&self, file: FileId) -> &SourceFile {
+        &self.files[file]
+    }
+}
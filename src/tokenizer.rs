@@ -1,23 +1,31 @@
-use super::utils::IsClosed;
-use regex::Regex;
+use super::error::*;
+use super::lexer::*;
+use super::span::{FileId, Span};
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use std::convert::TryFrom;
+use std::rc::Rc;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Tokens {
-    pub line_number: usize,
+    pub span: Span,
     pub token: Token,
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Def(Def),
+    Unpack(Unpack),
+    Type(TypeDef),
     Return(Box<Expr>),
     Expr(Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Lamda {
-    pub args: Vec<String>,
-    pub value: Vec<Tokens>,
+    // shared with every closure made from this lamda
+    pub args: Rc<Vec<String>>,
+    pub value: Rc<Vec<Tokens>>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -27,10 +35,25 @@ pub struct Decision {
     pub next: Option<Box<Decision>>,
 }
 
+// where a name lives at runtime : `index` in the frame `depth` frames up
+// from the current one. the tokenizer leaves it empty, the resolver fills it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Slot {
+    pub depth: usize,
+    pub index: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: String,
+    pub slot: Slot,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct FcCall {
-    pub args: Vec<Box<Expr>>,
+    pub args: Vec<Expr>,
     pub name: String,
+    pub slot: Slot,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -40,451 +63,1662 @@ pub struct Op {
     pub rhs: Box<Expr>,
 }
 
+impl Op {
+    // moves the operands out, leaving placeholders in their place
+    fn take_operands(&mut self, exprs: &mut Vec<Expr>) {
+        for expr in [&mut self.lhs, &mut self.rhs] {
+            let span = expr.span;
+            exprs.push(std::mem::replace(
+                &mut **expr,
+                Expr::new(ExprKind::Bool(false), span),
+            ));
+        }
+    }
+}
+
+// a chain like `x + x + ... + x` is parsed in a loop, but it nests as deep
+// as it is long and dropping it would recurse once per operator
+impl Drop for Op {
+    fn drop(&mut self) {
+        let mut exprs = Vec::new();
+        self.take_operands(&mut exprs);
+        while let Some(mut expr) = exprs.pop() {
+            if let ExprKind::Op(op) = &mut expr.kind {
+                op.take_operands(&mut exprs);
+            }
+        }
+    }
+}
+
+// `{ name: "a", age: 3 }`, or `{ person | age: 4 }` when there is a base
+// record to copy the other fields from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub base: Option<Box<Expr>>,
+    pub fields: Vec<(String, Expr)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub record: Box<Expr>,
+    pub name: String,
+}
+
+// `match value { pattern -> value ... }`, the first arm whose pattern fits
+// gives the value
+#[derive(Debug, Clone, PartialEq)]
+pub struct Match {
+    pub value: Box<Expr>,
+    pub arms: Vec<Arm>,
+}
+
+// the arm runs in a frame of its own holding what the pattern binds
+#[derive(Debug, Clone, PartialEq)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Vec<Tokens>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    pub kind: PatternKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PatternKind {
+    // `_`
+    Wildcard,
+    // any other lowercase name takes the value, `slot` is in the frame of
+    // the arm
+    Bind { name: String, slot: usize },
+    // a number, string or boolean
+    Literal(ExprKind),
+    // `[a, b]`, or `[a, b | rest]` with a pattern for the items left over
+    List(Vec<Pattern>, Option<Box<Pattern>>),
+    Tuple(Vec<Pattern>),
+    // fields the record must have, it may have others
+    Record(Vec<(String, Pattern)>),
+    // `Rect w h`, a pattern per field of the constructor
+    Constructor(Var, Vec<Pattern>),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Def {
     pub name: String,
     pub value: Box<Expr>,
+    pub span: Span,
+    // index in the current frame
+    pub slot: usize,
+}
+
+// `(q, r) : divmod 7 2`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unpack {
+    pub names: Vec<String>,
+    pub value: Box<Expr>,
+    pub span: Span,
+    // index of each name in the current frame
+    pub slots: Vec<usize>,
+}
+
+// one alternative of a `type`, like `Rect w h` in
+// `type Shape = Circle r | Rect w h`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Constructor {
+    pub type_name: String,
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDef {
+    pub name: String,
+    // shared with every value the constructor makes
+    pub constructors: Vec<Rc<Constructor>>,
+    pub spans: Vec<Span>,
+    // index of each constructor in the current frame
+    pub slots: Vec<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, PartialEq)]
-pub enum Expr {
-    Int(f64),
+pub enum ExprKind {
+    Int(i64),
+    BigInt(BigInt),
+    Float(f64),
+    Rational(BigRational),
     Str(String),
     Lamda(Lamda),
     Decision(Decision),
     FcCall(FcCall),
-    Call(String),
+    Call(Var),
     Scope(Vec<Tokens>),
     Bool(bool),
     Op(Op),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    List(Vec<Expr>),
+    Tuple(Vec<Expr>),
+    Record(Record),
+    Field(Field),
+    Match(Match),
 }
 
-#[derive(Debug, Clone, PartialEq)]
+impl Var {
+    pub fn new(name: String) -> Var {
+        Var {
+            name,
+            slot: Slot::default(),
+        }
+    }
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Expr {
+        Expr { kind, span }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum JOINT {
     ADD,
     SUB,
     MULT,
     DIV,
+    // floor division
+    IDIV,
+    MOD,
+    POW,
     GREAT,
     LESS,
+    GREATEQ,
+    LESSEQ,
     EQU,
+    // not equal
     NOT,
+    AND,
+    OR,
+}
+
+impl JOINT {
+    // higher binds tighter : arithmetic > comparison > equality > and > or
+    pub fn precedence(&self) -> u8 {
+        match self {
+            JOINT::POW => 7,
+            JOINT::MULT | JOINT::DIV | JOINT::IDIV | JOINT::MOD => 6,
+            JOINT::ADD | JOINT::SUB => 5,
+            JOINT::GREAT | JOINT::LESS | JOINT::GREATEQ | JOINT::LESSEQ => 4,
+            JOINT::EQU | JOINT::NOT => 3,
+            JOINT::AND => 2,
+            JOINT::OR => 1,
+        }
+    }
+
+    // `and` and `or` only look at their right hand side when the left one
+    // does not settle the result
+    pub fn short_circuits(&self) -> bool {
+        matches!(self, JOINT::AND | JOINT::OR)
+    }
+
+    // `2 ** 3 ** 2` is `2 ** 9`, everything else groups to the left
+    pub fn right_associative(&self) -> bool {
+        *self == JOINT::POW
+    }
 }
 
 #[derive(Debug)]
 pub struct Tokenizer {
     pub tokens: Vec<Tokens>,
-    lines: Vec<String>,
+    pub errors: Vec<TofError>,
+    src: String,
+    lexemes: Vec<Lexeme>,
     coverage: usize,
+    // whether a newline ends the current construct : false inside `( )`,
+    // true again inside a `{ }` block
+    newlines: Vec<bool>,
+    // the depth of `newlines` at which the value of a `match` is parsed,
+    // there a `{` opens the arms instead of passing a record
+    match_depth: Option<usize>,
+    prev_end: usize,
+    file: FileId,
 }
 
-lazy_static! {
-    static ref JOINS_REGEX: regex::Regex = Regex::new(r"([\+\-\\/\*><=!])").unwrap();
-    static ref FUNC_REGEX: regex::Regex = Regex::new(r"(\s+)").unwrap();
-    static ref CALL_REGEX: regex::Regex = Regex::new(r#"([^\+\-\\/\*><=!"\{\}])"#).unwrap();
-    static ref WHITE_REGEX: regex::Regex = Regex::new(r#"([\w])"#).unwrap();
-    static ref INT_REGEX: regex::Regex = Regex::new(r"([0-9.])").unwrap();
-    static ref BOOL_REGEX: regex::Regex = Regex::new(r"(TRUE|FALSE)").unwrap();
+// `Circle`, but not `TRUE` and `FALSE`
+fn is_constructor_name(name: &str) -> bool {
+    name.starts_with(char::is_uppercase) && name != "TRUE" && name != "FALSE"
 }
 
-impl Tokenizer {
-    pub fn new(file: &str) -> Tokenizer {
-        let lines: Vec<String> = file
-            .split("\n")
-            .collect::<Vec<&str>>()
-            .iter()
-            .map(|v| v.to_string())
-            .collect();
+fn unclosed(open: Span, delim: &str) -> TofError {
+    TofError::parse(format!("you forget to close `{}`", delim))
+        .at(open)
+        .with_help("it is opened here but never closed")
+}
+
+fn describe(kind: &LexKind) -> String {
+    match kind {
+        LexKind::Ident(name) => format!("`{}`", name),
+        LexKind::Int(int) => format!("`{}`", int),
+        LexKind::BigInt(int) => format!("`{}`", int),
+        LexKind::Rational(ratio) => format!("`{}r`", ratio),
+        LexKind::Float(float) => format!("`{:?}`", float),
+        LexKind::Str(string) => format!("{:?}", string),
+        LexKind::Operator(op) => format!("`{}`", op),
+        LexKind::Punct(punct) => format!("`{}`", punct),
+        LexKind::Newline => "end of line".to_string(),
+        LexKind::Comment(_) => "comment".to_string(),
+        LexKind::Error => "an invalid token".to_string(),
+        LexKind::Eof => "end of file".to_string(),
+    }
+}
 
-        let tokens: Vec<Tokens> = Vec::new();
+impl Tokenizer {
+    pub fn new(src: &str, file: FileId) -> Tokenizer {
         Tokenizer {
-            lines,
-            tokens,
+            tokens: Vec::new(),
+            errors: Vec::new(),
+            src: src.to_string(),
+            lexemes: Vec::new(),
             coverage: 0,
+            newlines: vec![true],
+            match_depth: None,
+            prev_end: 0,
+            file,
         }
     }
 
-    pub fn start(&mut self) {
+    // parses the whole file even when some statements are broken, `tokens`
+    // then holds every statement that did parse and all the errors are
+    // returned at once
+    pub fn start(&mut self) -> Result<(), Vec<TofError>> {
+        let mut lexer = Lexer::new(&self.src, self.file);
+        lexer.start();
+        self.lexemes = lexer.lexemes;
+        self.errors = lexer.errors;
+
+        match self.block_resolver(None) {
+            Ok(tokens) => self.tokens = tokens,
+            Err(err) => self.errors.push(err),
+        }
+
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            self.errors
+                .sort_by_key(|err| err.span.map(|span| span.start));
+            Err(self.errors.clone())
+        }
+    }
+
+    // index of the n-th lexeme from the current position that the parser
+    // cares about, comments are always skipped and newlines inside `( )`
+    fn index(&self, mut n: usize) -> usize {
+        let skip_newlines = !self.newlines.last().unwrap();
+        let mut i = self.coverage;
         loop {
-            match self.token_resolver(&self.lines[self.coverage].clone()) {
-                None => {}
-                Some(value) => match value {
-                    _ => {
-                        self.tokens.push(value);
+            match self.lexemes[i].kind {
+                LexKind::Comment(_) => {}
+                LexKind::Newline if skip_newlines => {}
+                LexKind::Eof => return i,
+                _ => {
+                    if n == 0 {
+                        return i;
                     }
+                    n -= 1;
+                }
+            }
+            i += 1;
+        }
+    }
+
+    fn lookahead(&self, n: usize) -> &Lexeme {
+        &self.lexemes[self.index(n)]
+    }
+
+    fn peek(&self) -> &Lexeme {
+        self.lookahead(0)
+    }
+
+    fn bump(&mut self) -> Lexeme {
+        let i = self.index(0);
+        let lexeme = self.lexemes[i].clone();
+        if lexeme.kind != LexKind::Eof {
+            self.coverage = i + 1;
+        }
+        self.prev_end = lexeme.span.end;
+        lexeme
+    }
+
+    fn is_punct(&self, punct: &'static str) -> bool {
+        self.peek().kind == LexKind::Punct(punct)
+    }
+
+    fn expect_punct(&mut self, punct: &'static str) -> TofResult<Lexeme> {
+        if self.is_punct(punct) {
+            Ok(self.bump())
+        } else {
+            Err(self.unexpected(&format!("expected `{}`", punct)))
+        }
+    }
+
+    fn unexpected(&self, expected: &str) -> TofError {
+        let lexeme = self.peek();
+        TofError::parse(format!("{}, found {}", expected, describe(&lexeme.kind))).at(lexeme.span)
+    }
+
+    fn skip_newlines(&mut self) {
+        while let LexKind::Newline | LexKind::Comment(_) = self.lexemes[self.coverage].kind {
+            self.coverage += 1;
+        }
+    }
+
+    // `||` continuing a decision chain may start on the next line
+    fn next_line_starts_with(&self, punct: &'static str) -> bool {
+        let mut i = self.coverage;
+        while let LexKind::Newline | LexKind::Comment(_) = self.lexemes[i].kind {
+            i += 1;
+        }
+        self.lexemes[i].kind == LexKind::Punct(punct)
+    }
+
+    fn span_from(&self, start: Span) -> Span {
+        Span::new(self.file, start.start, self.prev_end)
+    }
+
+    // statements up to the closing `}` of `open`, or to the end of file
+    fn block_resolver(&mut self, open: Option<Span>) -> TofResult<Vec<Tokens>> {
+        self.newlines.push(true);
+        let mut tokens = Vec::new();
+        loop {
+            self.skip_newlines();
+            match &self.peek().kind {
+                LexKind::Eof => match open {
+                    Some(span) => {
+                        return Err(unclosed(span, "{"));
+                    }
+                    None => break,
                 },
+                LexKind::Punct("}") if open.is_some() => break,
+                _ => {
+                    let depth = self.newlines.len();
+                    match self.token_resolver() {
+                        Ok(token) => tokens.push(token),
+                        Err(err) => {
+                            self.errors.push(err);
+                            self.newlines.truncate(depth);
+                            self.synchronize(open.is_some());
+                        }
+                    }
+                }
             }
-            self.coverage = self.coverage + 1;
-            if self.lines.len() <= self.coverage {
-                break;
+        }
+        self.newlines.pop();
+        Ok(tokens)
+    }
+
+    // skips the rest of a broken statement : up to the end of its line, or
+    // to the `}` closing the block it is in
+    fn synchronize(&mut self, in_block: bool) {
+        let mut depth = 0;
+        loop {
+            match self.lexemes[self.coverage].kind {
+                LexKind::Eof => break,
+                LexKind::Newline if depth == 0 => break,
+                LexKind::Punct("(") | LexKind::Punct("{") => depth += 1,
+                LexKind::Punct(")") | LexKind::Punct("}") if depth > 0 => depth -= 1,
+                LexKind::Punct("}") if in_block => break,
+                _ => {}
             }
+            self.coverage += 1;
         }
     }
 
-    pub fn token_resolver(&mut self, line: &str) -> Option<Tokens> {
-        let trimed_line = line.trim();
+    pub fn token_resolver(&mut self) -> TofResult<Tokens> {
+        let token = match (&self.peek().kind, &self.lookahead(1).kind) {
+            (LexKind::Ident(_), LexKind::Punct(":")) => self.def_resolver()?,
+            (LexKind::Ident(word), LexKind::Ident(_))
+                if word == "type" && self.lookahead(2).kind == LexKind::Operator("=") =>
+            {
+                self.type_resolver()?
+            }
+            (LexKind::Punct("("), _) if self.is_unpack_ahead() => self.unpack_resolver()?,
+            (LexKind::Operator(op), LexKind::Punct(":")) if op.chars().all(char::is_alphabetic) => {
+                return Err(
+                    TofError::parse(format!("`{}` is a keyword, not a name", op))
+                        .at(self.peek().span)
+                        .with_help("pick another name"),
+                );
+            }
+            _ => self.return_expr_resolver()?,
+        };
 
-        if trimed_line == "" || trimed_line.starts_with("//") {
-            None
-        } else if line.contains(":") {
-            self.def_resolver(&line)
-        } else {
-            self.return_expr_resolver(&line)
+        match self.peek().kind {
+            LexKind::Newline | LexKind::Eof | LexKind::Punct("}") => Ok(token),
+            _ => Err(self.unexpected("expected end of line")),
         }
     }
 
-    pub fn def_resolver(&mut self, line: &str) -> Option<Tokens> {
-        let (name, value) = line.split_once(":").unwrap();
+    pub fn def_resolver(&mut self) -> TofResult<Tokens> {
+        let name = self.bump();
+        self.bump();
+        let value = self.expression_resolver()?;
 
-        if CALL_REGEX.is_match(name.trim()) {
-            Some(Tokens {
-                line_number: self.coverage + 1,
-                token: Token::Def(Def {
-                    name: name.trim().to_string(),
-                    value: self.expression_resolver(&value),
-                }),
-            })
-        } else {
-            self.return_expr_resolver(&line)
+        let name_text = match name.kind {
+            LexKind::Ident(name) => name,
+            _ => unreachable!(),
+        };
+        Ok(Tokens {
+            span: name.span.to(value.span),
+            token: Token::Def(Def {
+                name: name_text,
+                value: Box::new(value),
+                span: name.span,
+                slot: 0,
+            }),
+        })
+    }
+
+    // `type Shape = Circle r | Rect w h`, the alternatives may also go one
+    // per line, each starting with `|`
+    fn type_resolver(&mut self) -> TofResult<Tokens> {
+        let start = self.bump().span;
+        let name = match self.bump().kind {
+            LexKind::Ident(name) => name,
+            _ => unreachable!(),
+        };
+        self.bump();
+        if !self.is_punct("|") && self.next_line_starts_with("|") {
+            self.skip_newlines();
+        }
+        if self.is_punct("|") {
+            self.bump();
+        }
+
+        let mut constructors: Vec<Rc<Constructor>> = Vec::new();
+        let mut spans = Vec::new();
+        loop {
+            let (ctor, span) = match self.peek().kind.clone() {
+                LexKind::Ident(ctor) => (ctor, self.bump().span),
+                _ => return Err(self.unexpected("expected a constructor name")),
+            };
+            if !is_constructor_name(&ctor) {
+                return Err(
+                    TofError::parse(format!("`{}` cannot name a constructor", ctor))
+                        .at(span)
+                        .with_help(
+                            "constructor names start with an uppercase letter, like `Circle`",
+                        ),
+                );
+            }
+            if constructors.iter().any(|c| c.name == ctor) {
+                return Err(
+                    TofError::parse(format!("the constructor `{}` is given twice", ctor)).at(span),
+                );
+            }
+            let mut fields: Vec<String> = Vec::new();
+            while let LexKind::Ident(field) = self.peek().kind.clone() {
+                let span = self.bump().span;
+                if fields.contains(&field) {
+                    return Err(
+                        TofError::parse(format!("the field `{}` is given twice", field)).at(span),
+                    );
+                }
+                fields.push(field);
+            }
+            constructors.push(Rc::new(Constructor {
+                type_name: name.clone(),
+                name: ctor,
+                fields,
+            }));
+            spans.push(self.span_from(span));
+
+            if !self.is_punct("|") && self.next_line_starts_with("|") {
+                self.skip_newlines();
+            }
+            if !self.is_punct("|") {
+                break;
+            }
+            self.bump();
         }
+
+        Ok(Tokens {
+            span: self.span_from(start),
+            token: Token::Type(TypeDef {
+                name,
+                constructors,
+                spans,
+                slots: Vec::new(),
+            }),
+        })
     }
 
-    pub fn return_expr_resolver(&mut self, mut line: &str) -> Option<Tokens> {
-        if line.trim().starts_with("~") {
-            line = &line[1..];
-            Some(Tokens {
-                line_number: self.coverage,
-                token: Token::Return(self.expression_resolver(&line)),
+    // `(a, b) :`, with at least one comma so that `(a) : 1` stays an error
+    fn is_unpack_ahead(&self) -> bool {
+        let mut n = 1;
+        let mut commas = 0;
+        while let LexKind::Ident(_) = self.lookahead(n).kind {
+            n += 1;
+            if self.lookahead(n).kind != LexKind::Punct(",") {
+                break;
+            }
+            n += 1;
+            commas += 1;
+        }
+        commas > 0
+            && self.lookahead(n).kind == LexKind::Punct(")")
+            && self.lookahead(n + 1).kind == LexKind::Punct(":")
+    }
+
+    fn unpack_resolver(&mut self) -> TofResult<Tokens> {
+        let open = self.bump().span;
+        let mut names: Vec<String> = Vec::new();
+        while let LexKind::Ident(name) = self.peek().kind.clone() {
+            let span = self.bump().span;
+            if names.contains(&name) {
+                return Err(TofError::parse(format!("`{}` is bound twice", name)).at(span));
+            }
+            names.push(name);
+            if self.is_punct(",") {
+                self.bump();
+            }
+        }
+        let span = open.to(self.expect_punct(")")?.span);
+        self.bump();
+        let value = self.expression_resolver()?;
+        Ok(Tokens {
+            span: span.to(value.span),
+            token: Token::Unpack(Unpack {
+                names,
+                value: Box::new(value),
+                span,
+                slots: Vec::new(),
+            }),
+        })
+    }
+
+    pub fn return_expr_resolver(&mut self) -> TofResult<Tokens> {
+        if self.is_punct("~") {
+            let start = self.bump().span;
+            let expr = self.expression_resolver()?;
+            Ok(Tokens {
+                span: start.to(expr.span),
+                token: Token::Return(Box::new(expr)),
             })
         } else {
-            Some(Tokens {
-                line_number: self.coverage,
-                token: Token::Expr(self.expression_resolver(&self.lines[self.coverage].clone())),
+            let expr = self.expression_resolver()?;
+            Ok(Tokens {
+                span: expr.span,
+                token: Token::Expr(Box::new(expr)),
             })
         }
     }
 
-    pub fn args_resolver(&mut self, value: &str) -> Vec<Box<Expr>> {
-        if value.trim() == "_" {
-            return Vec::new();
+    fn is_lamda_ahead(&self) -> bool {
+        let mut n = 0;
+        while let LexKind::Ident(_) = self.lookahead(n).kind {
+            n += 1;
         }
-        let splited = value.trim().split("").collect::<Vec<&str>>();
-        let mut args = Vec::new();
-        let mut arg = Vec::new();
+        self.lookahead(n).kind == LexKind::Punct("->")
+    }
 
-        let mut is_closed = IsClosed::new();
+    pub fn expression_resolver(&mut self) -> TofResult<Expr> {
+        if self.is_lamda_ahead() {
+            return self.scope_lamda_resolver();
+        }
+        let expr = self.binary_resolver(0)?;
+        if self.is_punct("?") {
+            return self.decision_resolver(expr);
+        }
+        Ok(expr)
+    }
 
-        let mut i = 0;
+    // precedence climbing : operands bind to the operator with the higher
+    // precedence, operators of equal precedence associate to the left
+    fn binary_resolver(&mut self, min: u8) -> TofResult<Expr> {
+        check_stack(self.peek().span)?;
+        let mut lhs = self.unary_resolver()?;
         loop {
-            if FUNC_REGEX.is_match(splited[i]) && is_closed.is() {
-                let exp = arg.join("");
-                args.push(self.expression_resolver(&exp));
-                arg = Vec::new();
+            let op = match self.peek().kind {
+                LexKind::Operator(op) => op,
+                // a bad character between two operands stands for the
+                // operator that was meant
+                LexKind::Error => {
+                    self.bump();
+                    if self.is_arg_start() {
+                        self.binary_resolver(u8::MAX)?;
+                    }
+                    continue;
+                }
+                _ => break,
+            };
+            let span = self.peek().span;
+            let joint = Tokenizer::op(op).map_err(|err| err.at(span))?;
+            let precedence = joint.precedence();
+            if precedence < min {
+                break;
+            }
+            self.bump();
+            let next = if joint.right_associative() {
+                precedence
+            } else {
+                precedence + 1
+            };
+            let rhs = self.binary_resolver(next)?;
+            let span = lhs.span.to(rhs.span);
+            lhs = Expr::new(
+                ExprKind::Op(Op {
+                    joint,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                }),
+                span,
+            );
+        }
+        Ok(lhs)
+    }
+
+    // `not` takes a whole comparison, `not a == b` is `not (a == b)`, and
+    // `-` a whole power, `-2 ** 2` is `-(2 ** 2)`
+    fn unary_resolver(&mut self) -> TofResult<Expr> {
+        match self.peek().kind {
+            LexKind::Operator("not") => {
+                let start = self.bump().span;
+                let expr = self.binary_resolver(JOINT::AND.precedence() + 1)?;
+                let span = start.to(expr.span);
+                Ok(Expr::new(ExprKind::Not(Box::new(expr)), span))
+            }
+            LexKind::Operator("-") => {
+                let start = self.bump().span;
+                let expr = self.binary_resolver(JOINT::POW.precedence())?;
+                Ok(Tokenizer::negate(expr, start))
             }
+            _ => self.node_resolver(),
+        }
+    }
+
+    // negative literals are folded right away
+    fn negate(expr: Expr, start: Span) -> Expr {
+        let span = start.to(expr.span);
+        let kind = match expr.kind {
+            ExprKind::Int(int) => match int.checked_neg() {
+                Some(int) => ExprKind::Int(int),
+                None => ExprKind::BigInt(-BigInt::from(int)),
+            },
+            ExprKind::BigInt(int) => match i64::try_from(-&int) {
+                Ok(int) => ExprKind::Int(int),
+                Err(_) => ExprKind::BigInt(-int),
+            },
+            ExprKind::Float(float) => ExprKind::Float(-float),
+            ExprKind::Rational(ratio) => ExprKind::Rational(-ratio),
+            _ => ExprKind::Neg(Box::new(expr)),
+        };
+        Expr::new(kind, span)
+    }
 
-            is_closed.check(splited[i]);
+    pub fn args_resolver(&mut self) -> TofResult<Vec<Expr>> {
+        if self.peek().kind == LexKind::Ident("_".to_string()) {
+            self.bump();
+            return Ok(Vec::new());
+        }
+        let mut args = Vec::new();
+        while self.is_arg_start() {
+            args.push(self.arg_resolver()?);
+        }
+        Ok(args)
+    }
 
-            arg.push(splited[i]);
-            i = i + 1;
+    fn is_arg_start(&self) -> bool {
+        if self.is_definition_ahead() {
+            return false;
+        }
+        self.is_negative_literal()
+            || matches!(
+                self.peek().kind,
+                LexKind::Ident(_)
+                    | LexKind::Int(_)
+                    | LexKind::BigInt(_)
+                    | LexKind::Float(_)
+                    | LexKind::Rational(_)
+                    | LexKind::Str(_)
+                    | LexKind::Error
+                    | LexKind::Punct("(")
+                    | LexKind::Punct("[")
+            )
+            || (self.is_punct("{") && self.match_depth != Some(self.newlines.len()))
+    }
 
-            if splited.len() == i && is_closed.is() {
-                args.push(self.node_resolver(&arg.join("")));
-                break;
-            } else if splited.len() == i {
+    // `name :` at the start of a line whose newline is skipped
+    fn is_definition_ahead(&self) -> bool {
+        let i = self.index(0);
+        self.lexemes[self.coverage..i]
+            .iter()
+            .any(|lexeme| lexeme.kind == LexKind::Newline)
+            && matches!(self.lexemes[i].kind, LexKind::Ident(_))
+            && self.lookahead(1).kind == LexKind::Punct(":")
+    }
+
+    // `f -1` passes `-1` while `n - 1` and `n-1` subtract : the `-` has to
+    // be glued to a number and apart from what comes before it, so `n -1`
+    // calls `n` with `-1`
+    fn is_negative_literal(&self) -> bool {
+        let minus = self.peek();
+        let next = self.lookahead(1);
+        minus.kind == LexKind::Operator("-")
+            && self.prev_end < minus.span.start
+            && minus.span.end == next.span.start
+            && matches!(
+                next.kind,
+                LexKind::Int(_) | LexKind::BigInt(_) | LexKind::Float(_) | LexKind::Rational(_)
+            )
+    }
+
+    // arguments are single nodes, `f a b` never calls `a` with `b`
+    fn arg_resolver(&mut self) -> TofResult<Expr> {
+        if self.is_negative_literal() {
+            let start = self.bump().span;
+            let expr = self.node_resolver()?;
+            return Ok(Tokenizer::negate(expr, start));
+        }
+        match self.peek().kind.clone() {
+            LexKind::Ident(name) if name != "TRUE" && name != "FALSE" => {
+                let span = self.bump().span;
+                self.field_resolver(Expr::new(ExprKind::Call(Var::new(name)), span))
             }
+            _ => self.node_resolver(),
         }
-        args
     }
 
-    pub fn scope_lamda_resolver(&mut self, part: &str) -> Box<Expr> {
-        let (args, steps) = part.split_once("->").unwrap();
-        let steps = steps.trim();
-        let value;
+    // `person.name`, `a.b.c` reads fields one after another, and `pair.0`
+    // the items of a tuple
+    fn field_resolver(&mut self, mut expr: Expr) -> TofResult<Expr> {
+        while self.is_punct(".") {
+            self.bump();
+            let name = match self.peek().kind.clone() {
+                LexKind::Ident(name) => name,
+                LexKind::Int(index) => index.to_string(),
+                _ => return Err(self.unexpected("expected a field name after `.`")),
+            };
+            self.bump();
+            let span = self.span_from(expr.span);
+            expr = Expr::new(
+                ExprKind::Field(Field {
+                    record: Box::new(expr),
+                    name,
+                }),
+                span,
+            );
+        }
+        Ok(expr)
+    }
 
-        if steps.starts_with("{") && steps.ends_with("}") {
-            let mut tokenizer = Tokenizer::new(&steps[1..steps.len() - 1]);
-            tokenizer.start();
-            value = tokenizer.tokens;
-        } else {
-            value = vec![Tokens {
-                line_number: self.coverage,
-                token: Token::Return(self.expression_resolver(steps)),
-            }];
+    pub fn scope_lamda_resolver(&mut self) -> TofResult<Expr> {
+        let start = self.peek().span;
+        let mut args = Vec::new();
+        while let LexKind::Ident(name) = self.peek().kind.clone() {
+            self.bump();
+            args.push(name);
         }
-        if args.trim() == "" {
-            Box::new(Expr::Scope(value))
-        } else {
-            let mut args: Vec<String> = args
-                .split_whitespace()
-                .collect::<Vec<&str>>()
-                .iter()
-                .map(|v| v.trim().to_string())
-                .collect();
+        self.expect_punct("->")?;
+        let value = self.body_resolver()?;
+        let span = self.span_from(start);
 
+        if args.is_empty() {
+            Ok(Expr::new(ExprKind::Scope(value), span))
+        } else {
             if args[0] == "_" {
                 args = Vec::new()
             }
-            Box::new(Expr::Lamda(Lamda { args, value }))
-        }
-    }
-
-    pub fn node_resolver(&mut self, part: &str) -> Box<Expr> {
-        let part = part.trim();
-        let n;
-
-        if part.starts_with("\"") && part.ends_with("\"") {
-            let part = part.trim_matches('\"').to_string();
-            n = Box::new(Expr::Str(part));
-        } else if part == "TRUE" {
-            n = Box::new(Expr::Bool(true));
-        } else if part == "FALSE" {
-            n = Box::new(Expr::Bool(false));
-        } else if part.starts_with("(") && part.ends_with(")") {
-            n = self.expression_resolver(&part[1..part.len() - 1]);
-        } else if FUNC_REGEX.is_match(&part) {
-            let splitted = part.split_whitespace().collect::<Vec<&str>>();
-            let name = splitted[0];
-
-            n = Box::new(Expr::FcCall(FcCall {
-                args: self.args_resolver(&part[name.len()..]),
-                name: name.to_string(),
-            }))
-        } else if INT_REGEX.is_match(&part) {
-            n = match part.parse() {
-                Ok(p) => Box::new(Expr::Int(p)),
-                Err(_) => {
-                    if CALL_REGEX.is_match(&part) {
-                        Box::new(Expr::Call(part.to_string()))
-                    } else {
-                        panic!("not a type : {} on line {}", part, self.coverage);
-                    }
+            Ok(Expr::new(
+                ExprKind::Lamda(Lamda {
+                    args: Rc::new(args),
+                    value: Rc::new(value),
+                }),
+                span,
+            ))
+        }
+    }
+
+    // what follows `->` : a block, a record or a single expression
+    fn body_resolver(&mut self) -> TofResult<Vec<Tokens>> {
+        if self.is_record_ahead() {
+            let record = self.record_resolver()?;
+            Ok(vec![Tokens {
+                span: record.span,
+                token: Token::Return(Box::new(record)),
+            }])
+        } else if self.is_punct("{") {
+            let open = self.bump().span;
+            let value = self.block_resolver(Some(open))?;
+            self.expect_punct("}")?;
+            Ok(value)
+        } else {
+            let expr = self.expression_resolver()?;
+            Ok(vec![Tokens {
+                span: expr.span,
+                token: Token::Return(Box::new(expr)),
+            }])
+        }
+    }
+
+    pub fn node_resolver(&mut self) -> TofResult<Expr> {
+        check_stack(self.peek().span)?;
+        let lexeme = self.peek().clone();
+
+        let kind = match lexeme.kind {
+            LexKind::Str(string) => {
+                self.bump();
+                ExprKind::Str(string)
+            }
+            LexKind::Int(int) => {
+                self.bump();
+                ExprKind::Int(int)
+            }
+            LexKind::BigInt(int) => {
+                self.bump();
+                ExprKind::BigInt(int)
+            }
+            LexKind::Float(float) => {
+                self.bump();
+                ExprKind::Float(float)
+            }
+            LexKind::Rational(ratio) => {
+                self.bump();
+                ExprKind::Rational(ratio)
+            }
+            LexKind::Ident(name) if name == "TRUE" || name == "FALSE" => {
+                self.bump();
+                ExprKind::Bool(name == "TRUE")
+            }
+            LexKind::Ident(name) => {
+                self.bump();
+                if name == "match" && self.is_arg_start() {
+                    return self.match_resolver(lexeme.span);
+                }
+                if self.is_arg_start() {
+                    ExprKind::FcCall(FcCall {
+                        args: self.args_resolver()?,
+                        name,
+                        slot: Slot::default(),
+                    })
+                } else {
+                    ExprKind::Call(Var::new(name))
                 }
             }
-        } else if CALL_REGEX.is_match(&part) {
-            n = Box::new(Expr::Call(part.to_string()))
-        } else {
-            panic!("not a type : {} on line {}", part, self.coverage);
+            LexKind::Punct("(") => {
+                self.bump();
+                self.newlines.push(false);
+                let expr = self.paren_resolver(lexeme.span);
+                self.newlines.pop();
+                return self.field_resolver(expr?);
+            }
+            LexKind::Punct("{") => return self.record_resolver(),
+            LexKind::Punct("[") => {
+                self.bump();
+                self.newlines.push(false);
+                let items = self.list_resolver(lexeme.span);
+                self.newlines.pop();
+                ExprKind::List(items?)
+            }
+            // stands for the value that was meant, the program never runs
+            LexKind::Error => {
+                self.bump();
+                ExprKind::Bool(false)
+            }
+            _ => return Err(self.unexpected("expected an expression")),
+        };
+        let expr = Expr::new(kind, self.span_from(lexeme.span));
+        match expr.kind {
+            ExprKind::FcCall(_) => Ok(expr),
+            _ => self.field_resolver(expr),
         }
-        n
     }
 
-    pub fn op(join: &str) -> JOINT {
-        match join {
-            "+" => JOINT::ADD,
-            "-" => JOINT::SUB,
-            "/" => JOINT::DIV,
-            "*" => JOINT::MULT,
-            ">" => JOINT::GREAT,
-            "<" => JOINT::LESS,
-            "=" => JOINT::EQU,
-            "!" => JOINT::NOT,
-            _ => panic!("operator not found : {}", join),
-        }
-    }
-
-    pub fn expression_resolver(&mut self, string: &str) -> Box<Expr> {
-        let mut splited: Vec<String> = string
-            .trim()
-            .split("")
-            .collect::<Vec<&str>>()
-            .iter()
-            .map(|v| v.to_string())
-            .collect();
-        let node;
-        let mut i = 0;
-
-        let mut parts = Vec::new();
-        let mut is_closed = IsClosed::new();
-        let mut cond: Option<String> = None;
+    // `match value {` and the arms up to `}`, one per line or separated by
+    // commas
+    fn match_resolver(&mut self, start: Span) -> TofResult<Expr> {
+        let outer = self.match_depth.replace(self.newlines.len());
+        let value = self.binary_resolver(0);
+        self.match_depth = outer;
+        let value = value?;
+        if !self.is_punct("{") {
+            return Err(self
+                .unexpected("expected `{`")
+                .with_help("the arms go in braces : `match x { 0 -> \"zero\", _ -> \"other\" }`"));
+        }
+        let open = self.bump().span;
+        self.newlines.push(true);
+        let arms = self.arms_resolver(open);
+        self.newlines.pop();
+        let arms = arms?;
+        if arms.is_empty() {
+            return Err(
+                TofError::parse("a match needs at least one arm".to_string())
+                    .at(self.span_from(start))
+                    .with_help("add `_ -> value` to give every value the same result"),
+            );
+        }
+        Ok(Expr::new(
+            ExprKind::Match(Match {
+                value: Box::new(value),
+                arms,
+            }),
+            self.span_from(start),
+        ))
+    }
+
+    fn arms_resolver(&mut self, open: Span) -> TofResult<Vec<Arm>> {
+        let mut arms = Vec::new();
         loop {
-            if JOINS_REGEX.is_match(&splited[i])
-                && is_closed.is()
-                && !is_closed.in_arrow
-                && !is_closed.in_cond
-            {
-                let part = parts.join("");
+            self.skip_newlines();
+            match self.peek().kind {
+                LexKind::Punct("}") => break,
+                LexKind::Eof => return Err(unclosed(open, "{")),
+                _ => {}
+            }
+            let pattern = self.pattern_resolver()?;
+            let mut names = Vec::new();
+            Tokenizer::check_bindings(&pattern, &mut names)?;
+            self.expect_punct("->")?;
+            let body = self.body_resolver()?;
+            arms.push(Arm { pattern, body });
+            match self.peek().kind {
+                LexKind::Punct(",") => {
+                    self.bump();
+                }
+                LexKind::Newline | LexKind::Punct("}") | LexKind::Eof => {}
+                _ => {
+                    return Err(self
+                        .unexpected("expected `,` or `}`")
+                        .with_help("put one arm per line, or separate them with commas"))
+                }
+            }
+        }
+        self.bump();
+        Ok(arms)
+    }
 
-                if splited[i] == "-" && splited[i + 1] == ">" {
-                    is_closed.in_arrow = true;
-                } else {
-                    let spliced = splited
-                        .splice(i + 1.., vec![])
-                        .collect::<Vec<String>>()
-                        .join("");
-
-                    node = Box::new(Expr::Op(Op {
-                        lhs: self.node_resolver(&part.as_str()),
-                        rhs: self.expression_resolver(&spliced),
-                        joint: Tokenizer::op(&splited[i]),
-                    }));
-                    break;
+    // a name bound twice in one pattern is an error, like in `(a, a) : t`
+    fn check_bindings<'a>(pattern: &'a Pattern, names: &mut Vec<&'a str>) -> TofResult<()> {
+        match &pattern.kind {
+            PatternKind::Wildcard | PatternKind::Literal(_) => {}
+            PatternKind::Bind { name, .. } => {
+                if names.contains(&name.as_str()) {
+                    return Err(
+                        TofError::parse(format!("`{}` is bound twice", name)).at(pattern.span)
+                    );
+                }
+                names.push(name);
+            }
+            PatternKind::List(items, rest) => {
+                for item in items.iter().chain(rest.as_deref()) {
+                    Tokenizer::check_bindings(item, names)?;
+                }
+            }
+            PatternKind::Tuple(items) | PatternKind::Constructor(_, items) => {
+                for item in items {
+                    Tokenizer::check_bindings(item, names)?;
+                }
+            }
+            PatternKind::Record(fields) => {
+                for (_, field) in fields {
+                    Tokenizer::check_bindings(field, names)?;
                 }
             }
+        }
+        Ok(())
+    }
 
-            if splited[i] == "|" && splited[i + 1] == "|" && is_closed.is() && !is_closed.in_arrow {
-                node = self.decision_resolver(
-                    &parts.join(""),
-                    cond,
-                    &splited
-                        .splice(i + 2..splited.len() - 1, vec![])
-                        .collect::<Vec<String>>()
-                        .join(""),
-                );
+    // `Rect w h` takes a pattern per field, everywhere else a constructor
+    // stands alone like in `Node Leaf v Leaf`
+    fn pattern_resolver(&mut self) -> TofResult<Pattern> {
+        match self.peek().kind.clone() {
+            LexKind::Ident(name) if is_constructor_name(&name) => {
+                let start = self.bump().span;
+                let mut args = Vec::new();
+                while self.is_pattern_start() {
+                    args.push(self.pattern_node()?);
+                }
+                Ok(Pattern {
+                    kind: PatternKind::Constructor(Var::new(name), args),
+                    span: self.span_from(start),
+                })
+            }
+            _ => self.pattern_node(),
+        }
+    }
+
+    fn is_pattern_start(&self) -> bool {
+        self.is_negative_pattern()
+            || matches!(
+                self.peek().kind,
+                LexKind::Ident(_)
+                    | LexKind::Int(_)
+                    | LexKind::BigInt(_)
+                    | LexKind::Float(_)
+                    | LexKind::Rational(_)
+                    | LexKind::Str(_)
+                    | LexKind::Punct("(")
+                    | LexKind::Punct("[")
+                    | LexKind::Punct("{")
+            )
+    }
+
+    // a `-` in a pattern can only be the sign of a number
+    fn is_negative_pattern(&self) -> bool {
+        self.peek().kind == LexKind::Operator("-")
+            && matches!(
+                self.lookahead(1).kind,
+                LexKind::Int(_) | LexKind::BigInt(_) | LexKind::Float(_) | LexKind::Rational(_)
+            )
+    }
+
+    fn pattern_node(&mut self) -> TofResult<Pattern> {
+        let lexeme = self.peek().clone();
+        let kind = match lexeme.kind {
+            LexKind::Ident(name) if name == "_" => {
+                self.bump();
+                PatternKind::Wildcard
+            }
+            LexKind::Ident(name) if name == "TRUE" || name == "FALSE" => {
+                self.bump();
+                PatternKind::Literal(ExprKind::Bool(name == "TRUE"))
+            }
+            LexKind::Ident(name) if is_constructor_name(&name) => {
+                self.bump();
+                PatternKind::Constructor(Var::new(name), Vec::new())
+            }
+            LexKind::Ident(name) => {
+                self.bump();
+                PatternKind::Bind { name, slot: 0 }
+            }
+            LexKind::Int(int) => {
+                self.bump();
+                PatternKind::Literal(ExprKind::Int(int))
+            }
+            LexKind::BigInt(int) => {
+                self.bump();
+                PatternKind::Literal(ExprKind::BigInt(int))
+            }
+            LexKind::Float(float) => {
+                self.bump();
+                PatternKind::Literal(ExprKind::Float(float))
+            }
+            LexKind::Rational(ratio) => {
+                self.bump();
+                PatternKind::Literal(ExprKind::Rational(ratio))
+            }
+            LexKind::Str(string) => {
+                self.bump();
+                PatternKind::Literal(ExprKind::Str(string))
+            }
+            LexKind::Operator("-") if self.is_negative_pattern() => {
+                self.bump();
+                let number = self.pattern_node()?;
+                match number.kind {
+                    PatternKind::Literal(kind) => {
+                        let number = Expr::new(kind, number.span);
+                        PatternKind::Literal(Tokenizer::negate(number, lexeme.span).kind)
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            LexKind::Punct("(") => {
+                self.bump();
+                self.newlines.push(false);
+                let pattern = self.paren_pattern(lexeme.span);
+                self.newlines.pop();
+                return pattern;
+            }
+            LexKind::Punct("[") => {
+                self.bump();
+                self.newlines.push(false);
+                let pattern = self.list_pattern(lexeme.span);
+                self.newlines.pop();
+                pattern?
+            }
+            LexKind::Punct("{") => {
+                self.bump();
+                self.newlines.push(false);
+                let pattern = self.record_pattern(lexeme.span);
+                self.newlines.pop();
+                pattern?
+            }
+            _ => return Err(self.unexpected("expected a pattern")),
+        };
+        Ok(Pattern {
+            kind,
+            span: self.span_from(lexeme.span),
+        })
+    }
+
+    // `(p)` is just `p`, like in expressions
+    fn paren_pattern(&mut self, open: Span) -> TofResult<Pattern> {
+        let first = self.pattern_resolver()?;
+        if !self.is_punct(",") {
+            self.close_paren(open)?;
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.is_punct(",") {
+            self.bump();
+            if self.is_punct(")") {
                 break;
             }
+            items.push(self.pattern_resolver()?);
+        }
+        self.close_paren(open)?;
+        Ok(Pattern {
+            kind: PatternKind::Tuple(items),
+            span: self.span_from(open),
+        })
+    }
 
-            is_closed.check(&splited[i]);
+    fn list_pattern(&mut self, open: Span) -> TofResult<PatternKind> {
+        let mut items = Vec::new();
+        let mut rest = None;
+        loop {
+            match self.peek().kind {
+                LexKind::Punct("]") => break,
+                LexKind::Eof => return Err(unclosed(open, "[")),
+                _ => {}
+            }
+            items.push(self.pattern_resolver()?);
+            if self.is_punct("|") {
+                self.bump();
+                rest = Some(Box::new(self.pattern_resolver()?));
+                if !self.is_punct("]") {
+                    return Err(self
+                        .unexpected("expected `]`")
+                        .with_help("the pattern after `|` takes every item left"));
+                }
+                break;
+            }
+            if self.is_punct(",") {
+                self.bump();
+            } else if !self.is_punct("]") && self.peek().kind != LexKind::Eof {
+                return Err(self
+                    .unexpected("expected `,`, `|` or `]`")
+                    .with_help("`[x | rest]` takes the first item and the rest of a list"));
+            }
+        }
+        self.bump();
+        Ok(PatternKind::List(items, rest))
+    }
 
-            if splited[i] == "?" && !is_closed.in_arrow {
-                is_closed.in_cond = true;
-                cond = Some(parts.join("").to_string());
-                parts = Vec::new();
+    // `{ name: n, age }`, a field alone binds a name of its own
+    fn record_pattern(&mut self, open: Span) -> TofResult<PatternKind> {
+        let mut fields: Vec<(String, Pattern)> = Vec::new();
+        loop {
+            let (name, span) = match self.peek().kind.clone() {
+                LexKind::Punct("}") => break,
+                LexKind::Eof => return Err(unclosed(open, "{")),
+                LexKind::Ident(name) => (name, self.bump().span),
+                _ => return Err(self.unexpected("expected a field name")),
+            };
+            let pattern = if self.is_punct(":") {
+                self.bump();
+                self.pattern_resolver()?
             } else {
-                parts.push(splited[i].clone());
-            }
-            i = i + 1;
-
-            if splited.len() == i && is_closed.is() {
-                if is_closed.in_cond {
-                    let nl = self.lines[self.coverage + 1].clone();
-                    if nl.trim().starts_with("||") && !self.if_lv_full() {
-                        self.coverage = self.coverage + 1;
-                        splited = [
-                            splited,
-                            nl.trim()
-                                .split("")
-                                .collect::<Vec<&str>>()
-                                .iter()
-                                .map(|v| v.to_string())
-                                .collect(),
-                        ]
-                        .concat();
-                    } else {
-                        node = self.decision_resolver(&parts.join(""), cond, "");
-                        break;
-                    }
-                } else {
-                    if is_closed.in_arrow {
-                        node = self.scope_lamda_resolver(&parts.join(""));
-                        break;
-                    } else {
-                        node = self.node_resolver(&parts.join(""));
-                        break;
+                Pattern {
+                    kind: PatternKind::Bind {
+                        name: name.clone(),
+                        slot: 0,
+                    },
+                    span,
+                }
+            };
+            if fields.iter().any(|(field, _)| *field == name) {
+                return Err(
+                    TofError::parse(format!("the field `{}` is given twice", name)).at(span),
+                );
+            }
+            fields.push((name, pattern));
+            if self.is_punct(",") {
+                self.bump();
+            } else if !self.is_punct("}") && self.peek().kind != LexKind::Eof {
+                return Err(self
+                    .unexpected("expected `,` or `}`")
+                    .with_help("fields are separated by commas : `{ name: n, age }`"));
+            }
+        }
+        self.bump();
+        Ok(PatternKind::Record(fields))
+    }
+
+    // `(a)` is just `a`, a comma makes a tuple : `(a, b)`, or `(a,)` for one
+    // item
+    fn paren_resolver(&mut self, open: Span) -> TofResult<Expr> {
+        let first = self.expression_resolver()?;
+        if !self.is_punct(",") {
+            self.close_paren(open)?;
+            return Ok(first);
+        }
+        let mut items = vec![first];
+        while self.is_punct(",") {
+            self.bump();
+            if self.is_punct(")") {
+                break;
+            }
+            items.push(self.expression_resolver()?);
+        }
+        self.close_paren(open)?;
+        Ok(Expr::new(ExprKind::Tuple(items), self.span_from(open)))
+    }
+
+    // newlines are skipped inside `( )`, so a `(` left open would take the
+    // definitions of the next lines in. one starting a line ends the `(`
+    fn close_paren(&mut self, open: Span) -> TofResult<()> {
+        match self.peek().kind {
+            _ if self.is_definition_ahead() => Err(unclosed(open, "(")),
+            LexKind::Eof => Err(unclosed(open, "(")),
+            _ => self.expect_punct(")").map(|_| ()),
+        }
+    }
+
+    // after `->` a `{` opens a block, unless the same line goes on with
+    // `name:` or `name |` : `x -> { name: x }` returns a record, while a
+    // block starting with a definition has it on the next line
+    fn is_record_ahead(&self) -> bool {
+        if !self.is_punct("{") {
+            return false;
+        }
+        let i = self.index(0);
+        let kind = |n: usize| self.lexemes.get(i + n).map(|lexeme| &lexeme.kind);
+        matches!(
+            (kind(1), kind(2)),
+            (Some(LexKind::Ident(_)), Some(LexKind::Punct(":")))
+                | (Some(LexKind::Ident(_)), Some(LexKind::Punct("|")))
+        )
+    }
+
+    fn record_resolver(&mut self) -> TofResult<Expr> {
+        let open = self.bump().span;
+        self.newlines.push(true);
+        let record = self.fields_resolver(open);
+        self.newlines.pop();
+        let record = Expr::new(ExprKind::Record(record?), self.span_from(open));
+        self.field_resolver(record)
+    }
+
+    // the fields after `{`, one per line or separated by commas
+    fn fields_resolver(&mut self, open: Span) -> TofResult<Record> {
+        self.skip_newlines();
+        let base = match (&self.peek().kind, &self.lookahead(1).kind) {
+            (LexKind::Ident(_), LexKind::Punct(":")) | (LexKind::Punct("}"), _) => None,
+            _ => {
+                let base = self.binary_resolver(0)?;
+                if !self.is_punct("|") {
+                    return Err(self
+                        .unexpected("expected `|`")
+                        .with_help("update a record with `{ record | field: value }`"));
+                }
+                self.bump();
+                Some(Box::new(base))
+            }
+        };
+
+        let mut fields: Vec<(String, Expr)> = Vec::new();
+        loop {
+            self.skip_newlines();
+            match self.peek().kind.clone() {
+                LexKind::Punct("}") => break,
+                LexKind::Eof => return Err(unclosed(open, "{")),
+                LexKind::Ident(name) => {
+                    let span = self.bump().span;
+                    self.expect_punct(":")?;
+                    let value = self.expression_resolver()?;
+                    if fields.iter().any(|(field, _)| *field == name) {
+                        return Err(TofError::parse(format!(
+                            "the field `{}` is given twice",
+                            name
+                        ))
+                        .at(span));
                     }
+                    fields.push((name, value));
+                }
+                _ => return Err(self.unexpected("expected a field name")),
+            }
+            match self.peek().kind {
+                LexKind::Punct(",") => {
+                    self.bump();
                 }
-            } else if splited.len() == i {
-                self.coverage = self.coverage + 1;
-                if self.if_lv_full() {
-                    panic!("you forget to close {:?} ", is_closed.unclosed());
+                LexKind::Newline | LexKind::Punct("}") | LexKind::Eof => {}
+                _ => {
+                    return Err(self
+                        .unexpected("expected `,` or `}`")
+                        .with_help("put one field per line, or separate them with commas"))
                 }
-                let nl = self.lines[self.coverage].clone();
-                splited = [
-                    splited,
-                    vec!["\n".to_string()],
-                    nl.trim()
-                        .split("")
-                        .collect::<Vec<&str>>()
-                        .iter()
-                        .map(|v| v.to_string())
-                        .collect(),
-                ]
-                .concat();
-            }
-        }
-
-        node
-    }
-
-    pub fn decision_resolver(
-        &mut self,
-        block: &str,
-        cond: Option<String>,
-        next_s: &str,
-    ) -> Box<Expr> {
-        let mut block = Tokenizer::new(&format!("~{}", block.to_string()));
-        block.start();
-        let block = block.tokens;
-
-        let mut is_closed = IsClosed::new();
-
-        let mut next_s = next_s
-            .split("")
-            .collect::<Vec<&str>>()
-            .iter()
-            .map(|v| {
-                is_closed.check(&v);
-                v.to_string()
-            })
-            .collect();
-
-        if !is_closed.is() {
-            loop {
-                self.coverage = self.coverage + 1;
-                let nl = self.lines[self.coverage].clone();
-                let nl = nl
-                    .trim()
-                    .split("")
-                    .collect::<Vec<&str>>()
-                    .iter()
-                    .map(|v| {
-                        is_closed.check(v);
-                        v.to_string()
-                    })
-                    .collect::<Vec<String>>()
-                    .join("");
-                next_s = [next_s, "\n".to_string(), nl].concat();
+            }
+        }
+        self.bump();
+        Ok(Record { base, fields })
+    }
 
-                if is_closed.is() {
-                    break;
+    // the items of `[a, b, c]` after the `[`, a trailing comma is allowed
+    fn list_resolver(&mut self, open: Span) -> TofResult<Vec<Expr>> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek().kind {
+                LexKind::Punct("]") => {
+                    self.bump();
+                    return Ok(items);
                 }
+                LexKind::Eof => return Err(unclosed(open, "[")),
+                _ => {}
+            }
+            items.push(self.expression_resolver()?);
+            if self.is_punct(",") {
+                self.bump();
+            } else if !self.is_punct("]") && self.peek().kind != LexKind::Eof {
+                return Err(self
+                    .unexpected("expected `,` or `]`")
+                    .with_help("list items are separated by commas : `[1, 2, 3]`"));
             }
         }
+    }
+
+    pub fn op(join: &str) -> TofResult<JOINT> {
+        match join {
+            "+" => Ok(JOINT::ADD),
+            "-" => Ok(JOINT::SUB),
+            "/" => Ok(JOINT::DIV),
+            "//" => Ok(JOINT::IDIV),
+            "%" => Ok(JOINT::MOD),
+            "**" => Ok(JOINT::POW),
+            "*" => Ok(JOINT::MULT),
+            ">" => Ok(JOINT::GREAT),
+            "<" => Ok(JOINT::LESS),
+            ">=" => Ok(JOINT::GREATEQ),
+            "<=" => Ok(JOINT::LESSEQ),
+            "==" => Ok(JOINT::EQU),
+            "!=" => Ok(JOINT::NOT),
+            // the old spellings of `==` and `!=`, still accepted so that
+            // older programs keep running
+            "=" => Ok(JOINT::EQU),
+            "!" => Ok(JOINT::NOT),
+            "and" => Ok(JOINT::AND),
+            "or" => Ok(JOINT::OR),
+            _ => Err(TofError::lex(format!("operator not found : {}", join))),
+        }
+    }
 
-        let lex;
-        if next_s != "" {
-            lex = self.expression_resolver(&next_s);
+    fn branch_resolver(&mut self) -> TofResult<Expr> {
+        if self.is_lamda_ahead() {
+            self.scope_lamda_resolver()
         } else {
-            panic!(
-                "else condition should be specified at line {}",
-                self.coverage
-            );
+            self.binary_resolver(0)
         }
+    }
 
-        let next;
+    pub fn decision_resolver(&mut self, cond: Expr) -> TofResult<Expr> {
+        self.expect_punct("?")?;
+        let block = self.branch_resolver()?;
 
-        match *lex {
-            Expr::Decision(d) => {
-                next = Some(Box::new(Decision {
-                    cond: d.cond,
-                    block: d.block,
-                    next: d.next,
-                }));
-            }
-            _ => {
-                next = Some(Box::new(Decision {
-                    cond: Box::new(Expr::Bool(true)),
-                    block: vec![Tokens {
-                        line_number: self.coverage,
-                        token: Token::Return(lex),
-                    }],
-                    next: None,
-                }));
-            }
-        }
-
-        Box::new(Expr::Decision(Decision {
-            cond: self.expression_resolver(&match cond {
-                Some(s) => s,
-                None => {
-                    panic!("please specify condition {}", self.coverage);
-                }
+        if !self.is_punct("||") && self.next_line_starts_with("||") {
+            self.skip_newlines();
+        }
+        if !self.is_punct("||") {
+            return Err(self
+                .unexpected("else condition should be specified")
+                .with_help("add a fallback branch : `cond ? a || b`"));
+        }
+        self.bump();
+        let lex = self.expression_resolver()?;
+        let span = cond.span.to(lex.span);
+
+        let next = match lex.kind {
+            ExprKind::Decision(d) => d,
+            _ => Decision {
+                cond: Box::new(Expr::new(ExprKind::Bool(true), lex.span)),
+                block: vec![Tokens {
+                    span: lex.span,
+                    token: Token::Return(Box::new(lex)),
+                }],
+                next: None,
+            },
+        };
+
+        Ok(Expr::new(
+            ExprKind::Decision(Decision {
+                cond: Box::new(cond),
+                block: vec![Tokens {
+                    span: block.span,
+                    token: Token::Return(Box::new(block)),
+                }],
+                next: Some(Box::new(next)),
             }),
-            block,
-            next,
-        }))
+            span,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(src: &str) -> Vec<Tokens> {
+        let mut tokenizer = Tokenizer::new(src, 0);
+        tokenizer.start().expect("the program parses");
+        tokenizer.tokens
+    }
+
+    // the expression on the only line of `src` with every operation in
+    // parentheses
+    fn grouped(src: &str) -> String {
+        match &parse(src)[0].token {
+            Token::Expr(expr) => show(expr),
+            other => panic!("expected an expression, got {:?}", other),
+        }
     }
 
-    pub fn if_lv_full(&self) -> bool {
-        self.lines.len() == self.coverage
+    fn show(expr: &Expr) -> String {
+        match &expr.kind {
+            ExprKind::Int(int) => int.to_string(),
+            ExprKind::BigInt(int) => int.to_string(),
+            ExprKind::Bool(bool) => bool.to_string(),
+            ExprKind::Call(var) => var.name.clone(),
+            ExprKind::Not(expr) => format!("(not {})", show(expr)),
+            ExprKind::Neg(expr) => format!("(-{})", show(expr)),
+            ExprKind::Op(op) => {
+                let symbol = match op.joint {
+                    JOINT::ADD => "+",
+                    JOINT::SUB => "-",
+                    JOINT::MULT => "*",
+                    JOINT::DIV => "/",
+                    JOINT::IDIV => "//",
+                    JOINT::MOD => "%",
+                    JOINT::POW => "**",
+                    JOINT::GREAT => ">",
+                    JOINT::LESS => "<",
+                    JOINT::GREATEQ => ">=",
+                    JOINT::LESSEQ => "<=",
+                    JOINT::EQU => "==",
+                    JOINT::NOT => "!=",
+                    JOINT::AND => "and",
+                    JOINT::OR => "or",
+                };
+                format!("({} {} {})", show(&op.lhs), symbol, show(&op.rhs))
+            }
+            other => panic!("unexpected expression {:?}", other),
+        }
+    }
+
+    #[test]
+    fn operators_of_equal_precedence_associate_to_the_left() {
+        assert_eq!(grouped("10 - 2 - 3 == 5"), "(((10 - 2) - 3) == 5)");
+        assert_eq!(grouped("8 / 4 * 2"), "((8 / 4) * 2)");
+    }
+
+    #[test]
+    fn arithmetic_binds_tighter_than_comparisons() {
+        assert_eq!(grouped("1 + 2 * 3 > 4"), "((1 + (2 * 3)) > 4)");
+        assert_eq!(grouped("a % 2 == 0"), "((a % 2) == 0)");
+    }
+
+    #[test]
+    fn powers_associate_to_the_right() {
+        assert_eq!(grouped("2 ** 3 ** 2"), "(2 ** (3 ** 2))");
+        assert_eq!(grouped("2 * 3 ** 2"), "(2 * (3 ** 2))");
+    }
+
+    #[test]
+    fn minus_takes_a_whole_power() {
+        assert_eq!(grouped("-2 ** 2"), "(-(2 ** 2))");
+        assert_eq!(grouped("-2 * 3"), "(-2 * 3)");
+    }
+
+    #[test]
+    fn negating_the_smallest_int_gives_a_big_int() {
+        assert_eq!(grouped("-9223372036854775808"), "-9223372036854775808");
+        assert_eq!(grouped("- -9223372036854775808"), "9223372036854775808");
+    }
+
+    #[test]
+    fn not_takes_a_whole_comparison() {
+        assert_eq!(grouped("not a == b"), "(not (a == b))");
+        assert_eq!(grouped("not a and b"), "((not a) and b)");
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(grouped("a or b and c"), "(a or (b and c))");
+        assert_eq!(grouped("a and b or c"), "((a and b) or c)");
+        assert_eq!(
+            grouped("x > 1 and y < 2 or z == 3"),
+            "(((x > 1) and (y < 2)) or (z == 3))"
+        );
+    }
+
+    // the body of the lamda defined by the only line of `src`
+    fn body(src: &str) -> Vec<Tokens> {
+        match parse(src).remove(0).token {
+            Token::Def(Def { value, .. }) => match value.kind {
+                ExprKind::Lamda(lamda) => lamda.value.to_vec(),
+                other => panic!("expected a lamda, got {:?}", other),
+            },
+            other => panic!("expected a definition, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn a_definition_on_the_next_line_is_a_block() {
+        let block = body("f : _ -> {\n  x : 1\n}\n");
+        assert!(matches!(block[0].token, Token::Def(_)));
+    }
+
+    #[test]
+    fn a_field_on_the_line_of_the_brace_is_a_record() {
+        let block = body("f : x -> { name: x,\n  age: 3 }\n");
+        match &block[0].token {
+            Token::Return(expr) => assert!(matches!(expr.kind, ExprKind::Record(_))),
+            other => panic!("expected a record, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fields_of_a_record_literal_are_read() {
+        match &parse("{ a: 1 }.a\n")[0].token {
+            Token::Expr(expr) => assert!(matches!(expr.kind, ExprKind::Field(_))),
+            other => panic!("expected a field, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn fields_can_be_separated_by_newlines() {
+        for src in ["r : {\n  a: 1\n  b: 2\n}\n", "r : { a: 1\n  b: 2 }\n"] {
+            match &parse(src)[0].token {
+                Token::Def(Def { value, .. }) => match &value.kind {
+                    ExprKind::Record(record) => assert_eq!(record.fields.len(), 2),
+                    other => panic!("expected a record, got {:?}", other),
+                },
+                other => panic!("expected a definition, got {:?}", other),
+            }
+        }
+    }
+
+    // the kind and the line of each error in `src`
+    fn errors(src: &str) -> Vec<(ErrorKind, usize)> {
+        let mut tokenizer = Tokenizer::new(src, 0);
+        let errors = tokenizer.start().expect_err("the program has errors");
+        let file = crate::span::SourceFile::new("test", src);
+        errors
+            .iter()
+            .map(|err| (err.kind, file.line_col(err.span.unwrap().start).0))
+            .collect()
+    }
+
+    #[test]
+    fn a_bad_token_is_reported_once() {
+        for src in [
+            "z : 3 @ 4\n",
+            "print (1e400)\n",
+            "print 1e400 2\n",
+            "x : @\n",
+        ] {
+            assert_eq!(errors(src), [(ErrorKind::Lex, 1)], "{}", src);
+        }
+    }
+
+    #[test]
+    fn every_syntax_error_is_reported() {
+        let src = "a : 1 +\nb : 2\nc : 3 )\n\nd : 4 $\ne : ]\nprint \"open\n";
+        assert_eq!(
+            errors(src),
+            [
+                (ErrorKind::Parse, 1),
+                (ErrorKind::Parse, 3),
+                (ErrorKind::Lex, 5),
+                (ErrorKind::Parse, 6),
+                (ErrorKind::Lex, 7),
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unclosed_paren_is_reported_where_it_opens() {
+        let src = "a : 1\nprint (f 1\nb : 2 )\nc : (1, 2\nd : 3\n";
+        assert_eq!(
+            errors(src),
+            [
+                (ErrorKind::Parse, 2),
+                (ErrorKind::Parse, 3),
+                (ErrorKind::Parse, 4),
+            ]
+        );
+        let err = &Tokenizer::new(src, 0).start().unwrap_err()[0];
+        assert_eq!(err.message, "you forget to close `(`");
+        assert_eq!(err.span.unwrap().start, src.find('(').unwrap());
+    }
+
+    #[test]
+    fn unclosed_delimiters_are_reported_where_they_open() {
+        for (src, delim) in [
+            ("f : _ -> {\n  x : 1\n", "{"),
+            ("r : { a: 1,\n  b: 2\n", "{"),
+            ("xs : [1,\n  2\n", "["),
+            ("y : match 1 {\n  1 -> 2\n", "{"),
+            ("z : (1,\n  2\n", "("),
+        ] {
+            let err = &Tokenizer::new(src, 0).start().unwrap_err()[0];
+            assert_eq!(err.message, format!("you forget to close `{}`", delim));
+            assert_eq!(err.span.unwrap().start, src.find(delim).unwrap(), "{}", src);
+        }
+    }
+
+    // blocks starting with a lamda used to be parsed again for every
+    // enclosing one, twice the time per level
+    #[test]
+    fn nested_blocks_parse_once() {
+        let mut body = "1".to_string();
+        for i in 0..24 {
+            body = format!("{{\n  g{} : x -> {}\n  ~ g{} 1\n}}", i, body, i);
+        }
+        parse(&format!("f : _ -> {}\n", body));
     }
 }
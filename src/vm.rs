@@ -0,0 +1,330 @@
+use crate::compiler::*;
+use crate::error::*;
+use crate::list::List;
+use crate::runtime::*;
+use crate::tokenizer::*;
+use std::rc::Rc;
+
+struct CallFrame {
+    function: Rc<Function>,
+    ip: usize,
+    env: Vars,
+    // height of the stack when the call was made
+    base: usize,
+    // the function and index of the instruction that made the call
+    caller: Option<(Rc<Function>, usize)>,
+    // set by `Worth`, returned in place of what the function ends with
+    worth: Option<Option<Variable>>,
+}
+
+// runs the bytecode of `Compiler` on a value stack. calls push a call frame
+// instead of recursing, variables live in the same frames the tree walker
+// uses so the slots of the resolver work unchanged
+pub struct Vm {
+    data: Vars,
+    stack: Vec<Option<Variable>>,
+    frames: Vec<CallFrame>,
+    max_depth: usize,
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm {
+            data: Vars::globals(),
+            stack: Vec::new(),
+            frames: Vec::new(),
+            max_depth: MAX_DEPTH,
+        }
+    }
+
+    // set from `--max-depth` of `tof run --vm`
+    pub fn with_max_depth(mut self, max_depth: usize) -> Vm {
+        self.max_depth = max_depth;
+        self
+    }
+
+    // runs top level tokens that went through `Resolver::start`
+    pub fn start(&mut self, tokens: &[Tokens]) -> TofResult<Option<Variable>> {
+        let function = Rc::new(Compiler::script(tokens)?);
+        self.frames.push(CallFrame {
+            function,
+            ip: 0,
+            env: self.data.clone(),
+            base: 0,
+            caller: None,
+            worth: None,
+        });
+        let result = self.run();
+        if result.is_err() {
+            self.stack.clear();
+            self.frames.clear();
+        }
+        result
+    }
+
+    fn frame(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().unwrap()
+    }
+
+    fn name(&mut self, name: usize) -> String {
+        self.frame().function.names[name].clone()
+    }
+
+    // the calls being run, innermost first
+    fn trace(&self) -> Vec<CallSite> {
+        self.frames
+            .iter()
+            .rev()
+            .filter_map(|frame| frame.caller.as_ref())
+            .map(|(function, ip)| match function.code[*ip] {
+                Instr::Call(_, name) | Instr::TailCall(_, name) => CallSite {
+                    name: function.names[name].clone(),
+                    span: function.spans[*ip],
+                },
+                _ => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn pop(&mut self) -> Option<Variable> {
+        self.stack.pop().unwrap()
+    }
+
+    fn run(&mut self) -> TofResult<Option<Variable>> {
+        loop {
+            let frame = self.frame();
+            let instr = frame.function.code[frame.ip];
+            let span = frame.function.spans[frame.ip];
+            frame.ip += 1;
+            match self.step(instr) {
+                Ok(Some(done)) => return Ok(done),
+                Ok(None) => {}
+                Err(err) => {
+                    let trace = self.trace();
+                    return Err(err.at(span).with_trace(trace));
+                }
+            }
+        }
+    }
+
+    // `Some` once the outermost frame returns
+    fn step(&mut self, instr: Instr) -> TofResult<Option<Option<Variable>>> {
+        match instr {
+            Instr::Const(i) => {
+                let var = self.frame().function.constants[i].clone();
+                self.stack.push(Some(var));
+            }
+            Instr::Void => self.stack.push(None),
+            Instr::Load(slot, name) => match self.frame().env.get(slot) {
+                Some(var) => self.stack.push(Some(var)),
+                None => return Err(TofError::undefined(&self.name(name))),
+            },
+            Instr::Define(slot, name) => match self.pop() {
+                Some(var) => self.frame().env.insert(slot, var),
+                None => return Err(TofError::void_definition(&self.name(name))),
+            },
+            Instr::Pop => {
+                self.pop();
+            }
+            Instr::Closure(i) => {
+                let frame = self.frame();
+                let var = Variable::Closure {
+                    function: frame.function.functions[i].clone(),
+                    env: frame.env.clone(),
+                };
+                self.stack.push(Some(var));
+            }
+            Instr::Call(argc, name) => self.call(argc, name, false)?,
+            Instr::TailCall(argc, name) => self.call(argc, name, true)?,
+            Instr::Return => {
+                let result = self.pop();
+                let frame = self.frames.pop().unwrap();
+                let result = frame.worth.unwrap_or(result);
+                self.stack.truncate(frame.base);
+                if self.frames.is_empty() {
+                    return Ok(Some(result));
+                }
+                self.stack.push(result);
+            }
+            Instr::Worth => {
+                let value = self.pop();
+                self.frame().worth.get_or_insert(value);
+            }
+            Instr::Enter => {
+                let frame = self.frame();
+                frame.env = frame.env.child();
+            }
+            Instr::Leave => {
+                let frame = self.frame();
+                frame.env = frame.env.parent().unwrap();
+            }
+            Instr::Jump(to) => self.frame().ip = to,
+            Instr::JumpIfFalse(to) => {
+                if self.pop() != Some(Variable::Bool(true)) {
+                    self.frame().ip = to;
+                }
+            }
+            Instr::ShortCircuit(joint, to) => {
+                let lhs = self.operand()?;
+                if Runtime::settles(joint, &lhs)? {
+                    self.frame().ip = to;
+                }
+                self.stack.push(Some(lhs));
+            }
+            Instr::Not => {
+                let var = self.operand()?;
+                self.stack
+                    .push(Some(Variable::Bool(!Runtime::truth(&var, "not")?)));
+            }
+            Instr::Neg => {
+                let var = self.operand()?;
+                self.stack.push(Some(Runtime::eval_neg(var)?));
+            }
+            Instr::List(len) => {
+                let at = self.stack.len() - len;
+                let items: Option<List> = self.stack.drain(at..).collect();
+                match items {
+                    Some(list) => self.stack.push(Some(Variable::List(list))),
+                    None => return Err(TofError::void_item("list")),
+                }
+            }
+            Instr::Tuple(len) => {
+                let at = self.stack.len() - len;
+                let items: Option<Vec<Variable>> = self.stack.drain(at..).collect();
+                match items {
+                    Some(items) => self
+                        .stack
+                        .push(Some(Variable::Tuple(Rc::new(Items(items))))),
+                    None => return Err(TofError::void_item("tuple")),
+                }
+            }
+            Instr::Unpack(len) => {
+                let value = self.pop();
+                let items = Runtime::unpack(value, len)?;
+                self.stack.extend(items.into_iter().map(Some));
+            }
+            Instr::Record(len, update) => {
+                let at = self.stack.len() - 2 * len;
+                let pairs: Vec<Option<Variable>> = self.stack.drain(at..).collect();
+                let mut fields = Vec::with_capacity(len);
+                for pair in pairs.chunks(2) {
+                    let name = match &pair[0] {
+                        Some(Variable::Str(name)) => name.clone(),
+                        _ => unreachable!(),
+                    };
+                    match &pair[1] {
+                        Some(var) => fields.push((name, var.clone())),
+                        None => return Err(TofError::void_field(&name)),
+                    }
+                }
+                let base = if update { Some(self.operand()?) } else { None };
+                self.stack.push(Some(Runtime::record(base, fields)?));
+            }
+            Instr::Field(name) => {
+                let record = self.operand()?;
+                let name = self.name(name);
+                self.stack.push(Some(Runtime::field(record, &name)?));
+            }
+            Instr::Match(pattern, next) => {
+                let value = match self.stack.last().unwrap() {
+                    Some(value) => value.clone(),
+                    None => return Err(TofError::void_match()),
+                };
+                let function = self.frame().function.clone();
+                let scope = self.frame().env.child();
+                if Runtime::matches(&function.patterns[pattern], &value, &scope)? {
+                    self.pop();
+                    self.frame().env = scope;
+                } else {
+                    self.frame().ip = next;
+                }
+            }
+            Instr::Unmatched => {
+                let value = self.operand()?;
+                return Err(TofError::no_match(value.type_name()));
+            }
+            Instr::Binary(joint) => {
+                let rhs = self.pop();
+                let lhs = self.pop();
+                match (lhs, rhs) {
+                    (Some(lhs), Some(rhs)) => {
+                        let var = Runtime::eval_op(joint, lhs, rhs)?;
+                        self.stack.push(Some(var));
+                    }
+                    _ => return Err(TofError::void_operand()),
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn operand(&mut self) -> TofResult<Variable> {
+        self.pop().ok_or_else(TofError::void_operand)
+    }
+
+    // a tail call replaces the frame of the caller, so loops written as
+    // recursion run in constant space
+    fn call(&mut self, argc: usize, name: usize, tail: bool) -> TofResult<()> {
+        let at = self.stack.len() - argc;
+        let args: Option<Vec<Variable>> = self.stack.drain(at..).collect();
+        let args = match args {
+            Some(args) => args,
+            None => return Err(TofError::void_argument(&self.name(name))),
+        };
+
+        match self.pop() {
+            Some(Variable::Rusty(fnc)) => {
+                let result = fnc(args)?;
+                self.stack.push(result);
+            }
+            Some(Variable::Constructor(ctor)) => {
+                let name = self.name(name);
+                let data = Runtime::construct(&ctor, args, &name)?;
+                self.stack.push(Some(data));
+            }
+            Some(Variable::Closure { function, env }) => {
+                if function.args.len() != args.len() {
+                    return Err(TofError::wrong_arity(
+                        &self.name(name),
+                        function.args.len(),
+                        args.len(),
+                    ));
+                }
+                let call_env = env.child();
+                for (slot, val) in args.into_iter().enumerate() {
+                    call_env.insert(slot, val);
+                }
+                let frame = self.frame();
+                let caller = Some((frame.function.clone(), frame.ip - 1));
+                if tail {
+                    frame.function = function;
+                    frame.ip = 0;
+                    frame.env = call_env;
+                    frame.caller = caller;
+                    let base = frame.base;
+                    self.stack.truncate(base);
+                } else {
+                    // the frame of the top level is not a call
+                    if self.frames.len() > self.max_depth {
+                        return Err(recursion_error(self.max_depth));
+                    }
+                    self.frames.push(CallFrame {
+                        function,
+                        ip: 0,
+                        env: call_env,
+                        base: self.stack.len(),
+                        caller,
+                        worth: None,
+                    });
+                }
+            }
+            _ => {
+                return Err(TofError::type_error(format!(
+                    "{} is not callable",
+                    self.name(name)
+                )))
+            }
+        }
+        Ok(())
+    }
+}
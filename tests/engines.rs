@@ -0,0 +1,345 @@
+// every program runs on the tree walker and on the vm, which must print the
+// same thing and fail with the same error at the same place. errors are
+// compared as rendered, so kind, message, span and trace all count
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+struct Outcome {
+    stdout: String,
+    stderr: String,
+    code: Option<i32>,
+}
+
+fn tof(file: &Path, vm: bool, args: &[&str], input: &str) -> Outcome {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_tof"));
+    command.arg("run").args(args);
+    if vm {
+        command.arg("--vm");
+    }
+    let mut child = command
+        .arg(file)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("tof runs");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        code: output.status.code(),
+    }
+}
+
+// `tof run` takes the name of the file without `.tof`
+fn write(name: &str, src: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tof-engines-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join(format!("{}.tof", name)), src).unwrap();
+    dir.join(name)
+}
+
+fn same_on_both(file: &Path, args: &[&str], input: &str) -> Outcome {
+    let walker = tof(file, false, args, input);
+    let vm = tof(file, true, args, input);
+    let name = file.display();
+    assert_eq!(walker.stdout, vm.stdout, "{} prints differently", name);
+    assert_eq!(walker.stderr, vm.stderr, "{} fails differently", name);
+    assert_eq!(walker.code, vm.code, "{} exits differently", name);
+    walker
+}
+
+// the whole output is pinned, both engines share the operators and the
+// values so they can be wrong the same way
+fn prints(file: &Path, args: &[&str], input: &str, expect: &str) {
+    let outcome = same_on_both(file, args, input);
+    assert_eq!(outcome.stderr, "", "{} failed", file.display());
+    assert_eq!(outcome.stdout, expect, "{} printed", file.display());
+}
+
+fn fails(file: &Path, args: &[&str], category: &str) {
+    let outcome = same_on_both(file, args, "");
+    assert_eq!(outcome.code, Some(1), "{} exited", file.display());
+    assert_eq!(outcome.stdout, "", "{} printed", file.display());
+    assert!(
+        outcome.stderr.starts_with(category),
+        "{} gave\n{}",
+        file.display(),
+        outcome.stderr
+    );
+}
+
+fn program(name: &str, src: &str, expect: &str) {
+    prints(&write(name, src), &[], "", expect);
+}
+
+fn example(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join(name)
+}
+
+#[test]
+fn examples() {
+    prints(&example("example"), &[], "", "");
+    prints(&example("bench/fib"), &[], "", "75025\n");
+
+    let calls = "
+print (factorial 10)
+print (fib 15)
+hello_world \"hi\"
+useLamda (msg -> print msg)
+print (greaterThan 2 1) (equal 1 1) (notEqual 1 1)
+";
+    let src = fs::read_to_string(example("example.tof")).unwrap() + calls;
+    program(
+        "example_calls",
+        &src,
+        "\
+55
+610
+hi
+hello to lamda
+truetruefalse
+",
+    );
+}
+
+#[test]
+fn numbers() {
+    program(
+        "numbers",
+        "
+print (7 // 2, -7 // 2, 7 % -3, -7 % 2, 7 / 2)
+print (2 ** 10, 2 ** -2, 2 ** 0.5, -2 ** 2, -(3))
+print (2 ** 64, (2 ** 64) / 3, 9223372036854775807 + 1)
+print (1 / 3, 3r / 4, 1/3r + 1/3r + 1/3r, 0.1 + 0.2, 1_000 * 1e3)
+",
+        "\
+(3, -4, -2, 1, 3)
+(1024, 1/4, 1.4142135623730951, -4, -3)
+(18446744073709551616, 6148914691236517205, 9223372036854775808)
+(0, 3/4, 1, 0.30000000000000004, 1000000.0)
+",
+    );
+}
+
+#[test]
+fn conditions_and_strings() {
+    program(
+        "conditions",
+        "
+sign : n -> (n > 0) ? \"+\" || (n < 0) ? \"-\" || \"0\"
+print (sign 3, sign -3, sign 0)
+print (1 == 1.0, \"a\" < \"b\", not 1 > 2 and TRUE or FALSE)
+print (\"con\" + \"cat\", len \"four\")
+",
+        "\
+(\"+\", \"-\", \"0\")
+(true, true, true)
+(\"concat\", 4)
+",
+    );
+}
+
+#[test]
+fn lamdas_scopes_and_tail_calls() {
+    program(
+        "lamdas",
+        "
+adder : n -> x -> x + n
+add2 : adder 2
+print (add2 40)
+count : n acc -> (n == 0) ? acc || count (n - 1) (acc + 1)
+print (count 100000 0)
+loop : n -> {
+  helper : x -> x + 1
+  ~ (n == 0) ? 0 || loop (helper n - 2)
+}
+print (loop 1000)
+block : x -> {
+  y : x * 2
+  ~ -> {
+    z : y + 1
+    ~ z
+  }
+}
+print (block 4)
+even : n -> (n == 0) ? TRUE || odd (n - 1)
+odd : n -> (n == 0) ? FALSE || even (n - 1)
+print (even 10001, odd 10001)
+x : 1
+shadow : _ -> {
+  y : x
+  x : 2
+  ~ (y, x)
+}
+print (shadow _)
+",
+        "\
+42
+100000
+0
+9
+(false, true)
+(1, 2)
+",
+    );
+}
+
+#[test]
+fn lists_records_and_tuples() {
+    program(
+        "compound",
+        "
+xs : cons 0 [1, 2, 3]
+print xs
+print (head xs, tail xs, nth xs 2, len xs, [1, 2] < [1, 3])
+p : { name: \"a\", age: 3 }
+q : { p | age: 4 }
+print p
+print q
+print (q.age, { a: 1 }.a, p == q, p == { age: 3, name: \"a\" })
+(d, m) : divmod 7 2
+print (d, m) (1,) ((1, \"a\").1)
+",
+        "\
+[0, 1, 2, 3]
+(0, [1, 2, 3], 2, 4, true)
+{ age: 3, name: \"a\" }
+{ age: 4, name: \"a\" }
+(4, 1, false, true)
+(3, 1)(1,)a
+",
+    );
+}
+
+#[test]
+fn types_and_match() {
+    program(
+        "types",
+        "
+type Shape = Circle r | Rect w h
+type Tree =
+  | Leaf
+  | Node left value right
+area : s -> match s {
+  Circle r -> 3 * r * r
+  Rect w h -> w * h
+}
+sum : t -> match t { Leaf -> 0, Node l v r -> sum l + v + sum r }
+print (area (Circle 2), area (Rect 3 4), (Rect 1 2).h)
+print (sum (Node (Node Leaf 1 Leaf) 2 Leaf))
+print (Node Leaf -1 Leaf)
+describe : x -> match x {
+  0 -> \"zero\"
+  [a, b | rest] -> (a, b, len rest)
+  (a, _) -> a
+  { name } -> name
+  _ -> \"other\"
+}
+print (describe 0, describe [1, 2, 3], describe (5, 6), describe { name: \"n\" }, describe 1.5)
+",
+        "\
+(12, 12, 2)
+3
+Node Leaf (-1) Leaf
+(\"zero\", (1, 2, 1), 5, \"n\", \"other\")
+",
+    );
+}
+
+#[test]
+fn errors() {
+    let cases = [
+        ("name", "print x\n", "name error"),
+        ("undefined", "f : _ -> y\nf _\ny : 1\n", "name error"),
+        (
+            "before",
+            "print \"side effect\"\nx : y + 1\ny : 2\n",
+            "name error",
+        ),
+        ("type", "print (1 + TRUE)\n", "type error"),
+        ("void", "f : _ -> {}\nx : f _\n", "type error"),
+        (
+            "void_operand",
+            "f : _ -> {}\nprint (1 + f _)\n",
+            "type error",
+        ),
+        (
+            "void_argument",
+            "f : _ -> {}\ng : x -> x\ng (f _)\n",
+            "type error",
+        ),
+        ("arity", "f : a b -> a\nf 1\n", "arity error"),
+        (
+            "division",
+            "\
+g : n -> n // 0
+f : n -> 1 + g n
+f 1
+",
+            "arithmetic error",
+        ),
+        ("float", "print (float (10 ** 400))\n", "arithmetic error"),
+        ("power", "print (3 ** 3000000000)\n", "arithmetic error"),
+        ("index", "print (nth [1] 3)\n", "index error"),
+        ("field", "print { a: 1 }.b\n", "name error"),
+        ("order", "print ({} < {})\n", "type error"),
+        ("unpack", "(a, b) : (1, 2, 3)\n", "type error"),
+        ("construct", "type T = A x\nprint (A 1 2)\n", "arity error"),
+        (
+            "no_match",
+            "\
+f : x -> match x { 1 -> 1 }
+f 2
+",
+            "match error",
+        ),
+        (
+            "pattern",
+            "\
+type T = A x
+match A 1 { A -> 1 }
+",
+            "arity error",
+        ),
+        ("parse", "x : (1 +\n", "parse error"),
+    ];
+    for (name, src, category) in cases {
+        fails(&write(name, src), &[], category);
+    }
+}
+
+#[test]
+fn recursion_limit() {
+    let file = write(
+        "depth",
+        "\
+f : n -> (n == 0) ? 0 || 1 + f (n - 1)
+print (f 500)
+",
+    );
+    fails(&file, &["--max-depth", "100"], "recursion error");
+}
+
+#[test]
+fn deep_expressions() {
+    let terms = vec!["x"; 5001].join(" + ");
+    program("terms", &format!("x : 1\nprint ({})\n", terms), "5001\n");
+    let parens = format!("print {}1{}\n", "(".repeat(200_000), ")".repeat(200_000));
+    fails(&write("parens", &parens), &[], "recursion error");
+}
+
+#[test]
+fn input() {
+    let file = write("scan_int", "n : int (scan \"n = \")\nprint (n * 2)\n");
+    prints(&file, &[], "21\n", "n = 42\n");
+}
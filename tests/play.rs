@@ -0,0 +1,69 @@
+// `tof play` runs each line as it is entered, with what the lines before it
+// defined
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+fn play(args: &[&str], lines: &str) -> (String, String) {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_tof"))
+        .arg("play")
+        .args(args)
+        .env("NO_COLOR", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("tof runs");
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(lines.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        String::from_utf8_lossy(&output.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn lamdas_use_names_of_later_lines() {
+    let (stdout, stderr) = play(&[], "f : _ -> g _\nprint (f _)\ng : _ -> 1\nprint (f _)\n");
+    assert!(
+        stderr.starts_with("name error: g is used before it is defined\n --> <play>:1:10\n"),
+        "{}",
+        stderr
+    );
+    assert_eq!(
+        stdout,
+        "welcome to interactive mode \npress : Ctrl-C to exit\n1\n^D\n"
+    );
+}
+
+#[test]
+fn other_names_must_be_defined_first() {
+    let (_, stderr) = play(&[], "x : y\ny : 1\nprint x\n");
+    assert!(
+        stderr.starts_with("name error: variable not in scope y\n"),
+        "{}",
+        stderr
+    );
+    assert!(
+        stderr.contains("name error: variable not in scope x\n"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn max_depth_limits_calls() {
+    let (_, stderr) = play(
+        &["--max-depth", "100"],
+        "f : n -> (n == 0) ? 0 || 1 + f (n - 1)\nprint (f 50)\nprint (f 200)\n",
+    );
+    assert!(
+        stderr.starts_with("recursion error: maximum recursion depth of 100 exceeded\n"),
+        "{}",
+        stderr
+    );
+}